@@ -0,0 +1,96 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use mseq::{
+    run_offline, Conductor, Context, DeteTrack, MidiConnection, MidiNote, Note, NullConnection,
+};
+use std::hint::black_box;
+
+// Number of steps in a sixteenth note.
+const SIXTEENTH_LEN: u32 = 6;
+
+// Number of clock ticks in a bar.
+const BAR_LEN: u32 = 96;
+
+// Track with one note at every sixteenth note, over `sixteenths` sixteenth notes.
+fn sequence(sixteenths: u32, channel_id: u8) -> DeteTrack {
+    let notes = (0..sixteenths)
+        .map(|i| {
+            let note = MidiNote::new(Note::from((i % 12) as u8), 4, 100);
+            (note, i * SIXTEENTH_LEN, SIXTEENTH_LEN / 2)
+        })
+        .collect();
+    DeteTrack::new(
+        sixteenths * SIXTEENTH_LEN,
+        notes,
+        Note::C,
+        channel_id,
+        "bench",
+    )
+}
+
+// Play 8 notes on each of the 16 channels at every tick.
+struct DenseConductor;
+
+impl Conductor for DenseConductor {
+    fn init(&mut self, context: &mut Context<impl MidiConnection>) {
+        context.start();
+    }
+
+    fn update(&mut self, context: &mut Context<impl MidiConnection>) {
+        for channel_id in 0..16 {
+            for i in 0..8 {
+                let note = MidiNote::new(Note::from(i), 4, 100);
+                context.midi.play_note(note, 3, channel_id);
+            }
+        }
+    }
+}
+
+// Play the tracks at every tick.
+struct TracksConductor<'a>(&'a mut [DeteTrack]);
+
+impl Conductor for TracksConductor<'_> {
+    fn init(&mut self, context: &mut Context<impl MidiConnection>) {
+        context.start();
+    }
+
+    fn update(&mut self, context: &mut Context<impl MidiConnection>) {
+        for track in self.0.iter_mut() {
+            context.midi.play_track(track);
+        }
+    }
+}
+
+fn update_dense(c: &mut Criterion) {
+    c.bench_function("bar of 16 channels x 8 notes", |b| {
+        b.iter(|| run_offline(DenseConductor, NullConnection, black_box(BAR_LEN)))
+    });
+}
+
+fn play_step_large(c: &mut Criterion) {
+    let mut tracks = [sequence(4096, 0)];
+    c.bench_function("bar of play_step 4096 notes", |b| {
+        b.iter(|| {
+            run_offline(
+                TracksConductor(&mut tracks),
+                NullConnection,
+                black_box(BAR_LEN),
+            )
+        })
+    });
+}
+
+fn stress(c: &mut Criterion) {
+    let mut tracks: Vec<DeteTrack> = (0..16).map(|i| sequence(64, i)).collect();
+    c.bench_function("16 tracks x 64 steps", |b| {
+        b.iter(|| {
+            run_offline(
+                TracksConductor(&mut tracks),
+                NullConnection,
+                black_box(64 * SIXTEENTH_LEN),
+            )
+        })
+    });
+}
+
+criterion_group!(benches, update_dense, play_step_large, stress);
+criterion_main!(benches);
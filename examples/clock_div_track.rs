@@ -9,7 +9,7 @@ impl Conductor for MyConductor {
         // The sequencer is on pause by default
         context.start();
         // Set the bpm to 157
-        context.set_bpm(157);
+        context.set_bpm(157.0);
     }
 
     fn update(&mut self, context: &mut mseq::Context<impl mseq::MidiConnection>) {
@@ -13,7 +13,7 @@ impl Track for MyTrack {
         midi_controller: &mut mseq::MidiController<impl mseq::MidiConnection>,
     ) {
         // Midi channel id to send the note to
-        if step % 8 == 0 {
+        if step.is_multiple_of(8) {
             // Choose a random note
             let note = MidiNote {
                 note: thread_rng().gen::<u8>().into(),
@@ -1,6 +1,14 @@
-use crate::{DeteTrack, MSeqError, MidiNote, Note};
+#[cfg(feature = "csv")]
+use crate::{read_csv, MSeqError};
+use crate::{DeteTrack, MidiNote, Note};
+#[cfg(feature = "csv")]
 use std::path::Path;
 
+/// CC parameter used for the [`AcidTrig::cutoff`] values (Sound Controller 5, Brightness).
+pub const ACID_CUTOFF_CC: u8 = 74;
+/// CC parameter used for the [`AcidTrig::resonance`] values (Sound Controller 2, Timbre).
+pub const ACID_RESONANCE_CC: u8 = 71;
+
 #[derive(Default, Clone, Copy, Debug, serde::Deserialize)]
 
 /// Timing mostly used in [`AcidTrig`] to generate acid tracks.
@@ -26,6 +34,14 @@ pub struct AcidTrig {
     pub slide: bool,
     /// Timing
     pub timing: Timing,
+    /// Filter cutoff value sent with the CC [`ACID_CUTOFF_CC`] at the start of the step. Optional
+    /// column in csv files.
+    #[serde(default)]
+    pub cutoff: Option<u8>,
+    /// Filter resonance value sent with the CC [`ACID_RESONANCE_CC`] at the start of the step.
+    /// Optional column in csv files.
+    #[serde(default)]
+    pub resonance: Option<u8>,
 }
 
 use Timing::*;
@@ -94,24 +110,32 @@ impl DeteTrack {
             }
         };
 
-        DeteTrack::new(6 * pattern.len() as u32, notes, root, channel_id, name)
+        let mut track = DeteTrack::new(6 * pattern.len() as u32, notes, root, channel_id, name);
+        for (step, trig) in pattern.iter().enumerate() {
+            let step = 6 * step as u32;
+            if let Some(cutoff) = trig.cutoff {
+                track.add_cc(ACID_CUTOFF_CC, cutoff, step);
+            }
+            if let Some(resonance) = trig.resonance {
+                track.add_cc(ACID_RESONANCE_CC, resonance, step);
+            }
+        }
+        track
     }
 
     /// Load an acid track from a csv file (`filename`). Refer to this [`example`] for an example
-    /// file. The `root` note is used for transposition. The track will be played on the MIDI
-    /// channel with `channel_id`.
+    /// file. The `cutoff` and `resonance` columns are optional (see [`AcidTrig`]). The `root` note
+    /// is used for transposition. The track will be played on the MIDI channel with `channel_id`.
     ///
     /// [`example`]: https://github.com/MF-Room/mseq/tree/main/examples/res/acid_0.csv
+    #[cfg(feature = "csv")]
     pub fn load_acid_from_file<P: AsRef<Path>>(
         filename: P,
         root: Note,
         channel_id: u8,
         name: &str,
     ) -> Result<Self, MSeqError> {
-        let mut rdr = csv::Reader::from_path(filename)?;
-        let pattern = rdr
-            .deserialize::<AcidTrig>()
-            .collect::<Result<Vec<_>, _>>()?;
+        let pattern = read_csv::<AcidTrig, _>(filename)?;
         Ok(Self::new_acid(pattern, root, channel_id, name))
     }
 }
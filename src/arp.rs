@@ -1,4 +1,7 @@
-use crate::{DeteTrack, MSeqError, MidiNote, Note};
+#[cfg(feature = "csv")]
+use crate::{read_csv, MSeqError};
+use crate::{DeteTrack, MidiNote, Note};
+#[cfg(feature = "csv")]
 use std::path::Path;
 
 /// Time division of the arpeggiator
@@ -44,6 +47,7 @@ impl DeteTrack {
     /// will be played on the MIDI channel with `channel_id`.
     ///
     /// [`example`]: https://github.com/MF-Room/mseq/tree/main/examples/res/arp_0.csv
+    #[cfg(feature = "csv")]
     pub fn load_arp_from_file<P: AsRef<Path>>(
         filename: P,
         div: ArpDiv,
@@ -51,10 +55,7 @@ impl DeteTrack {
         channel_id: u8,
         name: &str,
     ) -> Result<Self, MSeqError> {
-        let mut rdr = csv::Reader::from_path(filename)?;
-        let pattern = rdr
-            .deserialize::<MidiNote>()
-            .collect::<Result<Vec<_>, _>>()?;
+        let pattern = read_csv::<MidiNote, _>(filename)?;
         Ok(Self::new_arp(pattern, div, root, channel_id, name))
     }
 }
@@ -1,34 +1,81 @@
+use crate::SIXTEENTH_LEN;
 use std::time::{Duration, Instant};
 
 pub(crate) struct Clock {
     period_us: u64,
     next_clock_timestamp: Instant,
-    bpm: u8,
+    bpm: f32,
+    // Wait for the next tick, false when running offline.
+    realtime: bool,
+    // Delay of every second sixteenth note, as a fraction of a sixteenth note.
+    swing: f32,
+    // Position of the next tick in a pair of sixteenth notes.
+    phase: u32,
 }
 
 impl Clock {
-    pub(crate) fn new(bpm: u8) -> Self {
+    pub(crate) fn new(bpm: f32) -> Self {
         Self {
             period_us: Self::compute_period_us(bpm),
             next_clock_timestamp: Instant::now(),
             bpm,
+            realtime: true,
+            swing: 0.0,
+            phase: 0,
         }
     }
 
     pub fn tick(&mut self) {
-        self.next_clock_timestamp += Duration::from_micros(self.period_us);
+        self.next_clock_timestamp += Duration::from_micros(self.next_period_us());
+        self.phase = (self.phase + 1) % (2 * SIXTEENTH_LEN);
         let next_clock_timestamp = self.next_clock_timestamp;
 
-        let sleep_time = next_clock_timestamp - Instant::now();
-        spin_sleep::sleep(sleep_time);
+        if self.realtime {
+            let sleep_time = next_clock_timestamp - Instant::now();
+            spin_sleep::sleep(sleep_time);
+        }
+    }
+
+    // Process the ticks as fast as possible.
+    pub(crate) fn set_offline(&mut self) {
+        self.realtime = false;
     }
 
-    pub(crate) fn set_bpm(&mut self, bpm: u8) {
+    // Restart the clock from now, the next tick happens one period later.
+    pub(crate) fn reset(&mut self) {
+        self.next_clock_timestamp = Instant::now();
+    }
+
+    pub(crate) fn set_bpm(&mut self, bpm: f32) {
         self.bpm = bpm;
         self.period_us = Self::compute_period_us(self.bpm);
     }
 
-    fn compute_period_us(bpm: u8) -> u64 {
-        60 * 1000000 / 24 / bpm as u64
+    pub(crate) fn set_swing(&mut self, swing: f32) {
+        self.swing = swing;
+    }
+
+    // Align the swing to the sixteenth notes of the sequencer.
+    pub(crate) fn sync_phase(&mut self, step: u32) {
+        self.phase = step % (2 * SIXTEENTH_LEN);
+    }
+
+    // The ticks of the first sixteenth note of a pair are stretched and the ticks of the second
+    // one are compressed by the same amount, so the swing doesn't change the tempo.
+    fn next_period_us(&self) -> u64 {
+        let factor = if self.phase < SIXTEENTH_LEN {
+            1.0 + self.swing
+        } else {
+            1.0 - self.swing
+        };
+        (self.period_us as f32 * factor).round() as u64
+    }
+
+    pub(crate) fn get_bpm(&self) -> f32 {
+        self.bpm
+    }
+
+    fn compute_period_us(bpm: f32) -> u64 {
+        (60.0 * 1_000_000.0 / 24.0 / bpm as f64).round() as u64
     }
 }
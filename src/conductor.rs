@@ -16,4 +16,10 @@ pub trait Conductor {
     /// __Warning: if this function takes too long, the midi clock might be late. Be careful not to
     /// do any intensive computation, ot block the thread.__
     fn update(&mut self, context: &mut Context<impl MidiConnection>);
+    /// This function will be called at the first step of every bar (every 96 steps) while the
+    /// sequencer is playing, just before [`Conductor::update`]. It allows to run expensive
+    /// computations once per bar instead of at every midi clock cycle, scheduling the notes of the
+    /// whole bar with [`crate::MidiController::play_note_at`]. The default implementation does
+    /// nothing. Optional implementation.
+    fn update_bar(&mut self, _context: &mut Context<impl MidiConnection>) {}
 }
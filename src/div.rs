@@ -1,35 +1,124 @@
-use crate::{DeteTrack, MSeqError, MidiNote};
+#[cfg(feature = "csv")]
+use crate::{read_csv, MSeqError};
+use crate::{DeteTrack, MidiNote, BAR_LEN};
+use log::warn;
+#[cfg(feature = "csv")]
 use std::path::Path;
 
 #[derive(Debug, serde::Deserialize)]
 /// Struct used in [`DeteTrack::new_clock_div`] to generate a track with a pattern based on
-/// clock divisions.
+/// clock divisions. In csv files, `div` and `duration` can be written either as a number of clock
+/// messages (e.g., `6`) or as a musical division of a whole note (e.g., `1/16`, `3/4`), optionally
+/// dotted (e.g., `1/8.`) or triplet (e.g., `1/8t`).
 pub struct ClockDiv {
     /// Note triggered every `div` clock messages
+    #[serde(deserialize_with = "deserialize_steps")]
     pub div: u32,
     /// Number of clock messages
+    #[serde(deserialize_with = "deserialize_steps")]
     pub duration: u32,
+    /// Probability (from 0.0 to 1.0) of each trig to be played. If set to `None`, every trig is
+    /// played. Optional column in csv files.
+    #[serde(default)]
+    pub probability: Option<f32>,
+    /// Delay of every second trig, as a fraction (from 0.0 to 1.0) of `div`. Optional column in
+    /// csv files.
+    #[serde(default)]
+    pub swing: Option<f32>,
 }
 
 impl DeteTrack {
     /// Create a new [`DeteTrack`] with a patern made up of different [`ClockDiv`]. This pattern
-    /// triggers `note` on the MIDI channel with `channel_id`.
+    /// triggers `note` on the MIDI channel with `channel_id`. The trigs with a probability are
+    /// played using [`DeteTrack::add_alternatives`].
     pub fn new_clock_div(
         pattern: Vec<ClockDiv>,
         note: MidiNote,
         channel_id: u8,
         name: &str,
     ) -> Self {
-        let mut notes = vec![];
+        // (start step, length, probability)
+        let mut trigs = vec![];
         let mut len = 0;
         for p in pattern {
+            if p.div == 0 {
+                warn!("Clock division of 0, skipping {} steps", p.duration);
+                len += p.duration;
+                continue;
+            }
+            let delay = (p.swing.unwrap_or(0.0).clamp(0.0, 1.0) * p.div as f32).round() as u32;
+            let delay = delay.min(p.div - 1);
             let nb_trigs = p.duration / p.div;
             for i in 0..nb_trigs {
-                notes.push((note, len + i * p.div, p.div));
+                let (start, trig_len) = match (i % 2, i + 1 < nb_trigs) {
+                    (0, true) => (0, p.div + delay),
+                    (0, false) => (0, p.div),
+                    _ => (delay, p.div - delay),
+                };
+                trigs.push((len + i * p.div + start, trig_len, p.probability));
             }
             len += p.duration;
         }
-        Self::new(len, notes, note.note, channel_id, name)
+
+        let mut notes = vec![];
+        let mut alternatives = vec![];
+        for (start, trig_len, probability) in trigs {
+            match probability {
+                None => notes.push((note, start, trig_len)),
+                Some(p) => {
+                    let p = p.clamp(0.0, 1.0);
+                    alternatives.push((vec![(Some(note), p), (None, 1.0 - p)], start, trig_len));
+                }
+            }
+        }
+
+        let mut track = Self::new(len, notes, note.note, channel_id, name);
+        for (candidates, start, trig_len) in alternatives {
+            track.add_alternatives(candidates, start, trig_len);
+        }
+        track
+    }
+
+    /// Return a copy of the track that restarts from its beginning every `bars` bars. The pattern
+    /// is repeated until the reset, and the trigs that would cross the reset are cut. This
+    /// re-anchors patterns whose length is not a whole number of bars (e.g., a division of 5
+    /// sixteenth notes) to the downbeat, to build usable polyrhythms.
+    pub fn with_reset(&self, bars: u32) -> Self {
+        let len = bars.max(1) * BAR_LEN;
+        let pattern_len = self.len.max(1);
+        let repeats = len.div_ceil(pattern_len);
+        let cut = |start: u32, trig_len: u32| trig_len.min(len - start);
+
+        let mut track = self.clone();
+        track.len = len;
+        track.notes = (0..repeats)
+            .flat_map(|r| {
+                self.notes
+                    .iter()
+                    .map(move |n| (n.0, r * pattern_len + n.1 % pattern_len, n.2))
+            })
+            .filter(|n| n.1 < len)
+            .map(|n| (n.0, n.1, cut(n.1, n.2)))
+            .collect();
+        track.alt_notes = (0..repeats)
+            .flat_map(|r| {
+                self.alt_notes
+                    .iter()
+                    .map(move |n| (n.0.clone(), r * pattern_len + n.1 % pattern_len, n.2))
+            })
+            .filter(|n| n.1 < len)
+            .map(|n| (n.0, n.1, cut(n.1, n.2)))
+            .collect();
+        track.ccs = (0..repeats)
+            .flat_map(|r| {
+                self.ccs
+                    .iter()
+                    .map(move |c| (c.0, c.1, r * pattern_len + c.2 % pattern_len))
+            })
+            .filter(|c| c.2 < len)
+            .collect();
+        track.sort_by_step();
+        track
     }
 
     /// Load a clock division track from a csv file (`filename`). This pattern
@@ -37,16 +126,58 @@ impl DeteTrack {
     /// example file.
     ///
     /// [`example`]: https://github.com/MF-Room/mseq/tree/main/examples/res/clk_div_0.csv
+    #[cfg(feature = "csv")]
     pub fn load_clock_div_from_file<P: AsRef<Path>>(
         filename: P,
         note: MidiNote,
         channel_id: u8,
         name: &str,
     ) -> Result<Self, MSeqError> {
-        let mut rdr = csv::Reader::from_path(filename)?;
-        let pattern = rdr
-            .deserialize::<ClockDiv>()
-            .collect::<Result<Vec<_>, _>>()?;
+        let pattern = read_csv::<ClockDiv, _>(filename)?;
         Ok(Self::new_clock_div(pattern, note, channel_id, name))
     }
 }
+
+// Number of steps of a musical division such as "1/16", "1/8." or "1/4t".
+fn division_steps(division: &str) -> Option<u32> {
+    let division = division.trim();
+    let (division, num, den) = if let Some(d) = division.strip_suffix('.') {
+        (d, 3, 2)
+    } else if let Some(d) = division.strip_suffix('t') {
+        (d, 2, 3)
+    } else {
+        (division, 1, 1)
+    };
+    let (n, d) = division.split_once('/')?;
+    let n: u32 = n.trim().parse().ok()?;
+    let d: u32 = d.trim().parse().ok()?;
+    // A whole note lasts one bar.
+    let steps = BAR_LEN.checked_mul(n)?.checked_mul(num)?;
+    let len = d.checked_mul(den)?;
+    if len == 0 || !steps.is_multiple_of(len) {
+        return None;
+    }
+    Some(steps / len)
+}
+
+fn deserialize_steps<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    struct StepsVisitor;
+
+    impl serde::de::Visitor<'_> for StepsVisitor {
+        type Value = u32;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "a number of steps or a musical division (e.g., 1/16)")
+        }
+
+        fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<u32, E> {
+            u32::try_from(v).map_err(E::custom)
+        }
+
+        fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<u32, E> {
+            division_steps(v).ok_or_else(|| E::invalid_value(serde::de::Unexpected::Str(v), &self))
+        }
+    }
+
+    deserializer.deserialize_any(StepsVisitor)
+}
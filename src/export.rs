@@ -0,0 +1,104 @@
+use crate::track::TrackError;
+use crate::{DeteTrack, MSeqError, Track};
+use midly::num::{u4, u7};
+use midly::{Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+use std::path::Path;
+
+// One MIDI file tick per step: 24 ticks per quarter note.
+const TICKS_PER_BEAT: u16 = 24;
+
+/// Export a whole session to a midi file (`filename`) that can be opened in a DAW. The file
+/// contains one midi track per [`DeteTrack`] of `tracks`, with the name and the MIDI channel of the
+/// [`DeteTrack`], and a tempo of `bpm`. Each track is exported once (one loop), transposition and
+/// start step are taken into account. The CCs of the tracks are exported as MIDI control changes.
+/// The steps added with [`DeteTrack::add_alternatives`] are not exported since they are chosen
+/// while playing. Fails if a MIDI channel, a note or a CC value is out of range.
+pub fn export_session<P: AsRef<Path>>(
+    tracks: &[DeteTrack],
+    bpm: f32,
+    filename: P,
+) -> Result<(), MSeqError> {
+    let names: Vec<String> = tracks.iter().map(|t| t.get_name()).collect();
+
+    let tempo_track = vec![
+        TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::Meta(MetaMessage::Tempo(
+                ((60_000_000.0 / bpm.max(1.0)).round() as u32).into(),
+            )),
+        },
+        TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::Meta(MetaMessage::TimeSignature(4, 2, 24, 8)),
+        },
+        TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        },
+    ];
+
+    let error = |source| MSeqError::Track {
+        path: filename.as_ref().to_path_buf(),
+        source,
+    };
+    let mut smf_tracks = vec![tempo_track];
+    for (track, name) in tracks.iter().zip(&names) {
+        smf_tracks.push(export_track(track, name).map_err(error)?);
+    }
+
+    let smf = Smf {
+        header: Header::new(Format::Parallel, Timing::Metrical(TICKS_PER_BEAT.into())),
+        tracks: smf_tracks,
+    };
+    smf.save(&filename).map_err(|e| error(TrackError::Io(e)))
+}
+
+fn export_track<'a>(track: &DeteTrack, name: &'a str) -> Result<Vec<TrackEvent<'a>>, TrackError> {
+    let channel = u4::try_from(track.channel_id).ok_or(TrackError::OutOfRange)?;
+    let to_u7 = |v: u8| u7::try_from(v).ok_or(TrackError::OutOfRange);
+    let start = |step: u32| {
+        if track.len == 0 {
+            step
+        } else {
+            (step + track.start_step) % track.len
+        }
+    };
+
+    // (tick, order, message): at the same tick, note offs are sent first, then the CCs so that
+    // they apply to the notes starting at this tick.
+    let mut events = vec![];
+    for n in &track.notes {
+        let note = track.transpose.map_or(n.0, |t| n.0.transpose(t));
+        let (key, vel) = (to_u7(note.midi_value())?, to_u7(note.vel)?);
+        let start = start(n.1);
+        events.push((start, 2, MidiMessage::NoteOn { key, vel }));
+        events.push((start + n.2, 0, MidiMessage::NoteOff { key, vel: 0.into() }));
+    }
+    for &(parameter, value, step) in &track.ccs {
+        let (controller, value) = (to_u7(parameter)?, to_u7(value)?);
+        events.push((
+            start(step),
+            1,
+            MidiMessage::Controller { controller, value },
+        ));
+    }
+    events.sort_by_key(|e| (e.0, e.1));
+
+    let mut smf_track = vec![TrackEvent {
+        delta: 0.into(),
+        kind: TrackEventKind::Meta(MetaMessage::TrackName(name.as_bytes())),
+    }];
+    let mut tick = 0;
+    for (t, _, message) in events {
+        smf_track.push(TrackEvent {
+            delta: (t - tick).into(),
+            kind: TrackEventKind::Midi { channel, message },
+        });
+        tick = t;
+    }
+    smf_track.push(TrackEvent {
+        delta: track.len.saturating_sub(tick).into(),
+        kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+    });
+    Ok(smf_track)
+}
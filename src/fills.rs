@@ -0,0 +1,91 @@
+use crate::{DeteTrack, MidiNote, BAR_LEN, SIXTEENTH_LEN};
+
+// Number of steps in a beat.
+const BEAT_LEN: u32 = 24;
+// Number of steps in a thirty-second note.
+const THIRTY_SECOND_LEN: u32 = 3;
+
+/// Kind of fill generated by [`DeteTrack::new_fill`].
+#[derive(Clone, Copy, Debug)]
+#[non_exhaustive]
+pub enum Fill {
+    /// Each hit of the fill section is followed by a burst of thirty-second notes until the next
+    /// hit.
+    Burst,
+    /// Roll of `MidiNote` (usually a snare) going from sixteenth notes to thirty-second notes with
+    /// a velocity crescendo.
+    Roll(MidiNote),
+    /// The hits of the fill section are played backwards.
+    Reverse,
+}
+
+impl DeteTrack {
+    /// Create a one-bar fill from the first bar of this drum track. The first bar is played
+    /// unchanged except for its last `beats` beats (1 to 4) which are replaced by the `fill`. The
+    /// returned track is meant to be played once, in place of the bar preceding a section
+    /// boundary. It keeps the root, transposition and MIDI channel of this track.
+    pub fn new_fill(&self, fill: Fill, beats: u32, name: &str) -> Self {
+        let fill_start = BAR_LEN - beats.clamp(1, 4) * BEAT_LEN;
+
+        let (mut notes, tail): (Vec<_>, Vec<_>) = self
+            .first_bar_notes()
+            .into_iter()
+            .partition(|n| n.1 < fill_start);
+
+        match fill {
+            Fill::Burst => {
+                for (i, n) in tail.iter().enumerate() {
+                    let next = tail
+                        .iter()
+                        .skip(i + 1)
+                        .map(|m| m.1)
+                        .find(|&s| s > n.1)
+                        .unwrap_or(BAR_LEN);
+                    let len = n.2.min(THIRTY_SECOND_LEN);
+                    for s in (n.1..next).step_by(THIRTY_SECOND_LEN as usize) {
+                        notes.push((n.0, s, len));
+                    }
+                }
+            }
+            Fill::Roll(note) => {
+                let half = fill_start + (BAR_LEN - fill_start) / 2;
+                let steps = (fill_start..half)
+                    .step_by(SIXTEENTH_LEN as usize)
+                    .chain((half..BAR_LEN).step_by(THIRTY_SECOND_LEN as usize));
+                for s in steps {
+                    let progress = (s - fill_start) as f32 / (BAR_LEN - fill_start) as f32;
+                    let vel = (note.vel as f32 * (0.5 + 0.5 * progress)).round() as u8;
+                    let midi_note = MidiNote { vel, ..note };
+                    notes.push((midi_note, s, THIRTY_SECOND_LEN));
+                }
+            }
+            Fill::Reverse => {
+                for n in tail {
+                    let start = (fill_start + BAR_LEN - n.1)
+                        .saturating_sub(SIXTEENTH_LEN)
+                        .max(fill_start);
+                    notes.push((n.0, start, n.2.min(BAR_LEN - start)));
+                }
+            }
+        }
+
+        notes.sort_by_key(|n| n.1);
+        let mut track = DeteTrack::new(BAR_LEN, notes, self.root, self.channel_id, name);
+        track.transpose = self.transpose;
+        track
+    }
+
+    // Notes of the first bar of the track, repeating the track if it is shorter than a bar.
+    fn first_bar_notes(&self) -> Vec<(MidiNote, u32, u32)> {
+        if self.len == 0 {
+            return vec![];
+        }
+        let mut notes: Vec<_> = (0..BAR_LEN)
+            .step_by(self.len as usize)
+            .flat_map(|offset| self.notes.iter().map(move |n| (n.0, n.1 + offset, n.2)))
+            .filter(|n| n.1 < BAR_LEN)
+            .collect();
+        notes.sort_by_key(|n| n.1);
+        notes
+    }
+}
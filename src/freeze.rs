@@ -0,0 +1,143 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::midi_connection::MidiError;
+use crate::{DeteTrack, MidiConnection, MidiController, MidiNote, Track};
+use log::warn;
+
+// MIDI message recorded while freezing a track, with its MIDI channel.
+enum Event {
+    NoteOn(u8, u8, u8),
+    NoteOff(u8, u8),
+    Cc(u8, u8, u8),
+}
+
+// MidiConnection recording the notes and the CCs sent by a track.
+#[derive(Default, Clone)]
+struct FreezeConnection(Rc<RefCell<Vec<Event>>>);
+
+impl MidiConnection for FreezeConnection {
+    fn send_start(&mut self) -> Result<(), MidiError> {
+        Ok(())
+    }
+
+    fn send_continue(&mut self) -> Result<(), MidiError> {
+        Ok(())
+    }
+
+    fn send_stop(&mut self) -> Result<(), MidiError> {
+        Ok(())
+    }
+
+    fn send_clock(&mut self) -> Result<(), MidiError> {
+        Ok(())
+    }
+
+    fn send_song_position(&mut self, _position: u16) -> Result<(), MidiError> {
+        Ok(())
+    }
+
+    fn send_note_on(&mut self, channel_id: u8, note: u8, velocity: u8) -> Result<(), MidiError> {
+        self.0
+            .borrow_mut()
+            .push(Event::NoteOn(channel_id, note, velocity));
+        Ok(())
+    }
+
+    fn send_note_off(&mut self, channel_id: u8, note: u8) -> Result<(), MidiError> {
+        self.0.borrow_mut().push(Event::NoteOff(channel_id, note));
+        Ok(())
+    }
+
+    fn send_cc(&mut self, channel_id: u8, parameter: u8, value: u8) -> Result<(), MidiError> {
+        self.0
+            .borrow_mut()
+            .push(Event::Cc(channel_id, parameter, value));
+        Ok(())
+    }
+
+    fn send_program_change(&mut self, _channel_id: u8, _program: u8) -> Result<(), MidiError> {
+        Ok(())
+    }
+
+    fn send_pitch_bend(&mut self, _channel_id: u8, _value: u16) -> Result<(), MidiError> {
+        Ok(())
+    }
+
+    fn send_channel_pressure(&mut self, _channel_id: u8, _pressure: u8) -> Result<(), MidiError> {
+        Ok(())
+    }
+
+    fn send_poly_pressure(
+        &mut self,
+        _channel_id: u8,
+        _note: u8,
+        _pressure: u8,
+    ) -> Result<(), MidiError> {
+        Ok(())
+    }
+
+    fn send_sysex(&mut self, _data: &[u8]) -> Result<(), MidiError> {
+        Ok(())
+    }
+}
+
+impl DeteTrack {
+    /// Play `track` offline for `len` steps and freeze the notes and the CCs it plays on the MIDI
+    /// channel with `channel_id` into a [`DeteTrack`] of `len` steps played on the same channel.
+    /// This allows to bounce a generative or CPU-heavy track before playing it. The random choices
+    /// of `track` are made once, while freezing, with a random number generator seeded with `seed`
+    /// (e.g., [`MidiController::seed`] of the [`crate::Context`] to get reproducible runs). The
+    /// notes still playing after `len` steps are stopped at the end of the track. The root and the
+    /// name of `track` are kept.
+    pub fn freeze(track: &mut impl Track, len: u32, channel_id: u8, seed: u64) -> Self {
+        let conn = FreezeConnection::default();
+        let events = conn.0.clone();
+        let mut midi = MidiController::new(conn);
+        midi.set_seed(seed);
+
+        let mut notes = vec![];
+        let mut ccs = vec![];
+        // (note, velocity, start step) of the notes being played.
+        let mut playing: Vec<(u8, u8, u32)> = vec![];
+        let mut ignored = false;
+        let mut record = |step: u32, events: Vec<Event>| {
+            for event in events {
+                match event {
+                    Event::NoteOn(c, _, _) | Event::NoteOff(c, _) | Event::Cc(c, _, _)
+                        if c != channel_id =>
+                    {
+                        ignored = true
+                    }
+                    Event::NoteOn(_, note, vel) => playing.push((note, vel, step)),
+                    Event::NoteOff(_, note) => {
+                        if let Some(i) = playing.iter().position(|p| p.0 == note) {
+                            let (note, vel, start) = playing.remove(i);
+                            let note = MidiNote::from_midi_value(note, vel);
+                            notes.push((note, start, step - start));
+                        }
+                    }
+                    Event::Cc(_, parameter, value) => ccs.push((parameter, value, step)),
+                }
+            }
+        };
+
+        for step in 0..len {
+            track.play_step(step, &mut midi);
+            midi.update(step + 1);
+            record(step, events.take());
+        }
+        midi.stop_all_notes();
+        record(len, events.take());
+        if ignored {
+            warn!("Messages sent on other channels than {channel_id} are not frozen");
+        }
+
+        let name = track.get_name();
+        let mut frozen = DeteTrack::new(len, notes, track.get_root(), channel_id, &name);
+        for (parameter, value, step) in ccs {
+            frozen.add_cc(parameter, value, step);
+        }
+        frozen
+    }
+}
@@ -0,0 +1,96 @@
+use crate::{DeteTrack, MidiNote, SIXTEENTH_LEN};
+
+// Number of sixteenth notes in a bar, the length of a groove template.
+const GROOVE_LEN: usize = 16;
+
+/// Timing and velocity deviations of each sixteenth note of a bar, extracted from a performance
+/// with [`GrooveTemplate::extract`] and applied to a quantized track with
+/// [`DeteTrack::apply_groove`].
+#[derive(Clone, PartialEq, Debug)]
+pub struct GrooveTemplate {
+    // Average offset (in steps) of the notes from each sixteenth note.
+    timing: [f32; GROOVE_LEN],
+    // Average velocity of the notes of each sixteenth note, relative to the average velocity of
+    // all the notes.
+    velocity: [f32; GROOVE_LEN],
+}
+
+impl Default for GrooveTemplate {
+    fn default() -> Self {
+        Self {
+            timing: [0.0; GROOVE_LEN],
+            velocity: [1.0; GROOVE_LEN],
+        }
+    }
+}
+
+impl GrooveTemplate {
+    /// Extract the groove of `track`, e.g. a performance loaded with
+    /// [`DeteTrack::load_from_file`]. Each note is assigned to the nearest sixteenth note, and the
+    /// deviations of the notes of the same sixteenth note of the bar are averaged. The sixteenth
+    /// notes without any note have no deviation.
+    pub fn extract(track: &DeteTrack) -> Self {
+        let mut timing = [0.0; GROOVE_LEN];
+        let mut velocity = [0.0; GROOVE_LEN];
+        let mut count = [0; GROOVE_LEN];
+
+        for &(note, step, _) in &track.notes {
+            let (slot, offset) = nearest_sixteenth(step);
+            timing[slot] += offset as f32;
+            velocity[slot] += note.vel as f32;
+            count[slot] += 1;
+        }
+
+        let total = count.iter().sum::<u32>().max(1) as f32;
+        let mean_velocity = velocity.iter().sum::<f32>() / total;
+        let mut groove = Self::default();
+        for slot in 0..GROOVE_LEN {
+            if count[slot] > 0 && mean_velocity > 0.0 {
+                groove.timing[slot] = timing[slot] / count[slot] as f32;
+                groove.velocity[slot] = velocity[slot] / count[slot] as f32 / mean_velocity;
+            }
+        }
+        groove
+    }
+
+    /// Average offset (in steps) of the notes from the sixteenth note `slot` (0 to 15) of the bar.
+    pub fn timing(&self, slot: usize) -> f32 {
+        self.timing[slot % GROOVE_LEN]
+    }
+
+    /// Average velocity of the notes of the sixteenth note `slot` (0 to 15) of the bar, relative
+    /// to the average velocity of all the notes.
+    pub fn velocity(&self, slot: usize) -> f32 {
+        self.velocity[slot % GROOVE_LEN]
+    }
+}
+
+// Index of the nearest sixteenth note in the bar, and offset of the step from it.
+fn nearest_sixteenth(step: u32) -> (usize, i32) {
+    let sixteenth = (step + SIXTEENTH_LEN / 2) / SIXTEENTH_LEN;
+    let offset = step as i32 - (sixteenth * SIXTEENTH_LEN) as i32;
+    (sixteenth as usize % GROOVE_LEN, offset)
+}
+
+impl DeteTrack {
+    /// Return a copy of the track with `groove` applied to its notes: each note is moved and its
+    /// velocity is changed according to the nearest sixteenth note of the groove. `amount` (from
+    /// 0.0 to 1.0) scales the deviations, 0.0 leaves the track unchanged.
+    pub fn apply_groove(&self, groove: &GrooveTemplate, amount: f32) -> Self {
+        let amount = amount.clamp(0.0, 1.0);
+        let mut track = self.clone();
+        for n in &mut track.notes {
+            let (slot, _) = nearest_sixteenth(n.1);
+            let offset = (groove.timing[slot] * amount).round() as i64;
+            n.1 = (n.1 as i64 + offset).max(0) as u32;
+
+            let ratio = 1.0 + (groove.velocity[slot] - 1.0) * amount;
+            n.0 = MidiNote {
+                vel: (n.0.vel as f32 * ratio).round().clamp(1.0, 127.0) as u8,
+                ..n.0
+            };
+        }
+        track.sort_by_step();
+        track
+    }
+}
@@ -0,0 +1,84 @@
+use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use std::sync::Arc;
+
+// Value of the atomics when there is no pending request.
+const NONE: u8 = 0;
+// Value of the BPM atomic when there is no pending request, the bits of 0.0.
+const NO_BPM: u32 = 0;
+
+const START: u8 = 1;
+const PAUSE: u8 = 2;
+const RESUME: u8 = 3;
+const QUIT: u8 = 4;
+
+/// Transport request sent through a [`ContextHandle`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum HandleRequest {
+    Start,
+    Pause,
+    Resume,
+    Quit,
+}
+
+#[derive(Default)]
+struct HandleInner {
+    // Bits of the requested BPM.
+    bpm: AtomicU32,
+    transport: AtomicU8,
+}
+
+/// Handle to control the sequencer from other threads (e.g., a GUI or a network controller),
+/// returned by [`crate::Context::handle`]. The handle can be cloned and sent to other threads.
+///
+/// The requests are stored in atomics and applied by the sequencer at the beginning of the next
+/// clock tick, before [`crate::Conductor::update`] is called. They never block the sequencer. If
+/// several transport requests are sent during the same clock tick, only the last one is applied.
+#[derive(Clone, Default)]
+pub struct ContextHandle {
+    inner: Arc<HandleInner>,
+}
+
+impl ContextHandle {
+    /// Request the sequencer to set the BPM, see [`crate::Context::set_bpm`]. A BPM of 0.0 is
+    /// ignored.
+    pub fn set_bpm(&self, bpm: f32) {
+        self.inner.bpm.store(bpm.to_bits(), Ordering::Release);
+    }
+
+    /// Request the sequencer to start, see [`crate::Context::start`].
+    pub fn start(&self) {
+        self.inner.transport.store(START, Ordering::Release);
+    }
+
+    /// Request the sequencer to pause, see [`crate::Context::pause`].
+    pub fn pause(&self) {
+        self.inner.transport.store(PAUSE, Ordering::Release);
+    }
+
+    /// Request the sequencer to resume, see [`crate::Context::resume`].
+    pub fn resume(&self) {
+        self.inner.transport.store(RESUME, Ordering::Release);
+    }
+
+    /// Request the sequencer to exit, see [`crate::Context::quit`].
+    pub fn quit(&self) {
+        self.inner.transport.store(QUIT, Ordering::Release);
+    }
+
+    pub(crate) fn take_bpm(&self) -> Option<f32> {
+        match self.inner.bpm.swap(NO_BPM, Ordering::Acquire) {
+            NO_BPM => None,
+            bpm => Some(f32::from_bits(bpm)),
+        }
+    }
+
+    pub(crate) fn take_request(&self) -> Option<HandleRequest> {
+        match self.inner.transport.swap(NONE, Ordering::Acquire) {
+            START => Some(HandleRequest::Start),
+            PAUSE => Some(HandleRequest::Pause),
+            RESUME => Some(HandleRequest::Resume),
+            QUIT => Some(HandleRequest::Quit),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use crate::{DeteTrack, MSeqError, MidiNote, Note};
+use log::debug;
+use std::collections::HashMap;
+use std::path::Path;
+use thiserror::Error;
+
+// Hydrogen uses 48 ticks per quarter note and mseq uses 24.
+const TICKS_PER_STEP: u32 = 2;
+// Length of the notes that have no length specified (one-shot notes) in Hydrogen.
+const DEFAULT_NOTE_LEN: u32 = 6;
+
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum HydrogenError {
+    #[error("Failed to read hydrogen file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Xml error: {0}")]
+    Xml(#[from] roxmltree::Error),
+    #[error("Pattern not found")]
+    PatternNotFound,
+    #[error("Missing or invalid value: {0}")]
+    BadValue(&'static str),
+}
+
+impl DeteTrack {
+    /// Load a drum track from a [`Hydrogen`] song (`.h2song`) or pattern (`.h2pattern`) file
+    /// (`filename`). `pattern` is the name of the pattern to load, if set to `None` the first
+    /// pattern of the file is loaded. `map` associates the Hydrogen instrument ids to the notes
+    /// that will be played, the velocity of each note is scaled by the velocity of the Hydrogen
+    /// note. The instruments missing from `map` are ignored, which allows to load instruments on
+    /// different MIDI channels into different tracks. The track will be played on the MIDI
+    /// channel with `channel_id`.
+    ///
+    /// [`Hydrogen`]: http://hydrogen-music.org
+    pub fn load_hydrogen_from_file<P: AsRef<Path>>(
+        filename: P,
+        pattern: Option<&str>,
+        map: &HashMap<u32, MidiNote>,
+        channel_id: u8,
+        name: &str,
+    ) -> Result<Self, MSeqError> {
+        Self::parse_hydrogen_file(&filename, pattern, map, channel_id, name).map_err(|source| {
+            MSeqError::Hydrogen {
+                path: filename.as_ref().to_path_buf(),
+                source,
+            }
+        })
+    }
+
+    fn parse_hydrogen_file<P: AsRef<Path>>(
+        filename: P,
+        pattern: Option<&str>,
+        map: &HashMap<u32, MidiNote>,
+        channel_id: u8,
+        name: &str,
+    ) -> Result<Self, HydrogenError> {
+        let text = fs_err::read_to_string(filename)?;
+        let doc = roxmltree::Document::parse(&text)?;
+
+        let pattern = doc
+            .descendants()
+            .filter(|n| {
+                n.has_tag_name("pattern") && n.children().any(|c| c.has_tag_name("noteList"))
+            })
+            .find(|p| {
+                pattern.is_none_or(|name| {
+                    child_text(*p, "name").or(child_text(*p, "pattern_name")) == Some(name)
+                })
+            })
+            .ok_or(HydrogenError::PatternNotFound)?;
+
+        let size: u32 = child_value(pattern, "size")?;
+        let mut notes = vec![];
+
+        let note_list = pattern.children().find(|n| n.has_tag_name("noteList"));
+        for note in note_list
+            .iter()
+            .flat_map(|l| l.children())
+            .filter(|n| n.has_tag_name("note"))
+        {
+            let instrument: u32 = child_value(note, "instrument")?;
+            let Some(midi_note) = map.get(&instrument) else {
+                debug!("Ignoring hydrogen instrument {instrument}");
+                continue;
+            };
+
+            let position: u32 = child_value(note, "position")?;
+            let velocity: f32 = child_value(note, "velocity")?;
+            let length: i32 = child_value(note, "length")?;
+
+            let len = if length < 0 {
+                DEFAULT_NOTE_LEN
+            } else {
+                (length as u32 / TICKS_PER_STEP).max(1)
+            };
+            let vel = (midi_note.vel as f32 * velocity.clamp(0.0, 1.0)).round() as u8;
+            notes.push((
+                MidiNote { vel, ..*midi_note },
+                position / TICKS_PER_STEP,
+                len,
+            ));
+        }
+
+        // Drum tracks are not meant to be transposed, the root is arbitrary.
+        Ok(DeteTrack::new(
+            size / TICKS_PER_STEP,
+            notes,
+            Note::C,
+            channel_id,
+            name,
+        ))
+    }
+}
+
+fn child_text<'a>(node: roxmltree::Node<'a, '_>, tag: &str) -> Option<&'a str> {
+    node.children()
+        .find(|n| n.has_tag_name(tag))
+        .and_then(|n| n.text())
+}
+
+fn child_value<T: std::str::FromStr>(
+    node: roxmltree::Node,
+    tag: &'static str,
+) -> Result<T, HydrogenError> {
+    child_text(node, tag)
+        .and_then(|t| t.trim().parse().ok())
+        .ok_or(HydrogenError::BadValue(tag))
+}
@@ -0,0 +1,54 @@
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use log::error;
+
+/// Handle to a job started with [`crate::Context::spawn_job`]. The result of the job can be
+/// retrieved with [`Job::try_result`] without blocking the sequencer.
+pub struct Job<R> {
+    receiver: Receiver<R>,
+    finished: bool,
+}
+
+impl<R: Send + 'static> Job<R> {
+    pub(crate) fn spawn(job: impl FnOnce() -> R + Send + 'static) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            // The receiver may have been dropped if the result is not needed anymore.
+            let _ = sender.send(job());
+        });
+        Self {
+            receiver,
+            finished: false,
+        }
+    }
+}
+
+impl<R> Job<R> {
+    /// Returns the result of the job if it has finished, `None` otherwise. This method never
+    /// blocks, so it can be called in [`crate::Conductor::update`]. The result is returned only
+    /// once, the next calls return `None`.
+    pub fn try_result(&mut self) -> Option<R> {
+        if self.finished {
+            return None;
+        }
+        match self.receiver.try_recv() {
+            Ok(result) => {
+                self.finished = true;
+                Some(result)
+            }
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                error!("Job panicked before returning a result");
+                self.finished = true;
+                None
+            }
+        }
+    }
+
+    /// Returns `true` once the result has been returned by [`Job::try_result`], or if the job
+    /// panicked.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
@@ -14,6 +14,16 @@
 //! You can find some examples in the [`examples`] directory.
 //!
 //! [`examples`]: https://github.com/MF-Room/mseq/tree/main/examples
+//!
+//! # Features
+//!
+//! The following features are enabled by default, disable them to build a smaller sequencer:
+//! - `csv`: load tracks from csv files (e.g., [`DeteTrack::load_acid_from_file`]).
+//! - `hydrogen`: load drum tracks from Hydrogen patterns
+//!   ([`DeteTrack::load_hydrogen_from_file`]).
+//! - `midly`: load tracks from midi files and export sessions to midi files (e.g.,
+//!   [`DeteTrack::load_from_file`], [`TrackLibrary`]).
+//! - `promptly`: ask for the MIDI output port in [`run`] when it is not given.
 
 #![warn(missing_docs)]
 
@@ -22,40 +32,226 @@ mod arp;
 mod clock;
 mod conductor;
 mod div;
+#[cfg(feature = "midly")]
+mod export;
+mod fills;
+mod freeze;
+mod groove;
+mod handle;
+#[cfg(feature = "hydrogen")]
+mod hydrogen;
+mod job;
+#[cfg(feature = "midly")]
+mod library;
 mod midi_connection;
 mod midi_controller;
 mod note;
+mod offline;
+mod params;
+mod random;
+mod scaled;
+mod tempo;
 mod tests;
 mod track;
+mod validate;
 
 // Interface
-pub use acid::{AcidTrig, Timing};
+pub use acid::{AcidTrig, Timing, ACID_CUTOFF_CC, ACID_RESONANCE_CC};
 pub use arp::ArpDiv;
 pub use conductor::Conductor;
 pub use div::ClockDiv;
-pub use midi_connection::MidiConnection;
+#[cfg(feature = "midly")]
+pub use export::export_session;
+pub use fills::Fill;
+pub use groove::GrooveTemplate;
+pub use handle::ContextHandle;
+pub use job::Job;
+#[cfg(feature = "midly")]
+pub use library::TrackLibrary;
+pub use midi_connection::{MidiConnection, NullConnection, TextConnection};
 use midi_connection::{MidiError, MidirConnection};
-pub use midi_controller::{MidiController, MidiNote};
+pub use midi_controller::{
+    MidiController, MidiNote, MpeZone, NoteOrder, RangePolicy, RetriggerPolicy,
+};
 pub use note::Note;
+pub use offline::{compare_conductors, run_offline};
+pub use params::{Param, Params};
+pub use scaled::ScaledTrack;
+pub use tempo::TempoMap;
+#[cfg(feature = "midly")]
+pub use track::OverlapPolicy;
 pub use track::{DeteTrack, Track};
+#[cfg(any(feature = "csv", feature = "midly"))]
+pub use validate::TrackFormat;
+pub use validate::TrackIssue;
 
 use clock::Clock;
+use handle::HandleRequest;
+use log::{info, warn};
+#[cfg(feature = "csv")]
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+#[cfg(feature = "csv")]
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
-const DEFAULT_BPM: u8 = 120;
+const DEFAULT_BPM: f32 = 120.0;
+// Range of the BPM of the sequencer.
+const MIN_BPM: f32 = 1.0;
+const MAX_BPM: f32 = 1000.0;
+// Number of steps in a bar.
+const BAR_LEN: u32 = 96;
+// Number of steps in a sixteenth note, the unit of the MIDI song position.
+const SIXTEENTH_LEN: u32 = 6;
 
-/// Error type of mseq
+/// Error type of mseq. The error messages start with the error code returned by
+/// [`MSeqError::code`].
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum MSeqError {
     /// Error type related to MIDI messages
-    #[error("Midi error [{}: {}]", file!(), line!())]
+    #[error("[E01] Midi error: {0}")]
     Midi(#[from] MidiError),
     /// Error type related to CSV file parsing
-    #[error("Failed to parse csv file [{}: {}]\n\t{0}", file!(), line!())]
-    Reading(#[from] csv::Error),
+    #[cfg(feature = "csv")]
+    #[error(
+        "[E02] Failed to parse csv file {}{}\n\t{source}",
+        path.display(),
+        csv_location(*line, *record, *field)
+    )]
+    Reading {
+        /// Path of the csv file
+        path: PathBuf,
+        /// Line of the csv file where the error occurred (starting at 1)
+        line: Option<u64>,
+        /// Record of the csv file where the error occurred (starting at 1, after the header)
+        record: Option<u64>,
+        /// Field of the record where the error occurred (starting at 0)
+        field: Option<u64>,
+        /// Underlying csv error
+        source: csv::Error,
+    },
     /// Error type related to MIDI file parsing
-    #[error("Failed to parse midi file [{}: {}]\n\t{0}", file!(), line!())]
-    Track(#[from] track::TrackError),
+    #[error("[E03] Failed to parse midi file {}\n\t{source}", path.display())]
+    Track {
+        /// Path of the midi file
+        path: PathBuf,
+        /// Underlying error
+        source: track::TrackError,
+    },
+    /// Error type related to Hydrogen file parsing
+    #[cfg(feature = "hydrogen")]
+    #[error("[E04] Failed to parse hydrogen file {}\n\t{source}", path.display())]
+    Hydrogen {
+        /// Path of the hydrogen file
+        path: PathBuf,
+        /// Underlying error
+        source: hydrogen::HydrogenError,
+    },
+    /// Error type related to the track library
+    #[cfg(feature = "midly")]
+    #[error("[E05] Track {name} not found in the library")]
+    Library {
+        /// Name of the track
+        name: String,
+    },
+}
+
+impl MSeqError {
+    /// Code identifying the kind of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            MSeqError::Midi(_) => "E01",
+            #[cfg(feature = "csv")]
+            MSeqError::Reading { .. } => "E02",
+            MSeqError::Track { .. } => "E03",
+            #[cfg(feature = "hydrogen")]
+            MSeqError::Hydrogen { .. } => "E04",
+            #[cfg(feature = "midly")]
+            MSeqError::Library { .. } => "E05",
+        }
+    }
+
+    #[cfg(feature = "csv")]
+    pub(crate) fn csv<P: AsRef<Path>>(path: P, source: csv::Error) -> Self {
+        let field = match source.kind() {
+            csv::ErrorKind::Deserialize { err, .. } => err.field(),
+            _ => None,
+        };
+        MSeqError::Reading {
+            path: path.as_ref().to_path_buf(),
+            line: source.position().map(|p| p.line()),
+            record: source.position().map(|p| p.record()),
+            field,
+            source,
+        }
+    }
+}
+
+#[cfg(feature = "csv")]
+fn csv_location(line: Option<u64>, record: Option<u64>, field: Option<u64>) -> String {
+    let mut location = String::new();
+    if let Some(line) = line {
+        location += &format!(" at line {line}");
+    }
+    if let Some(record) = record {
+        location += &format!(", record {record}");
+    }
+    if let Some(field) = field {
+        location += &format!(", field {field}");
+    }
+    location
+}
+
+// Read all the records of a csv file.
+#[cfg(feature = "csv")]
+pub(crate) fn read_csv<D: DeserializeOwned, P: AsRef<Path>>(
+    filename: P,
+) -> Result<Vec<D>, MSeqError> {
+    let mut rdr = csv::Reader::from_path(&filename).map_err(|e| MSeqError::csv(&filename, e))?;
+    rdr.deserialize::<D>()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| MSeqError::csv(&filename, e))
+}
+
+/// State of the transport of the sequencer, see [`Context::transport_state`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TransportState {
+    /// The sequencer has not been started yet, or it has been quit.
+    Stopped,
+    /// The sequencer has been paused with [`Context::pause`].
+    Paused,
+    /// The sequencer is playing, the step is increasing at each clock tick.
+    Playing,
+}
+
+/// Message sent when the sequencer resumes after a pause, see [`Context::set_resume_policy`].
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ResumePolicy {
+    /// Send a MIDI continue message, the sequencer continues from the step at which it was
+    /// paused.
+    #[default]
+    Continue,
+    /// Send a MIDI start message, the sequencer restarts from step 0 as with [`Context::start`].
+    /// Use it with devices that ignore the MIDI continue message.
+    Restart,
+}
+
+// Stage of a resync started with Context::resync.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Resync {
+    Requested,
+    Stopped,
+    Positioned,
+}
+
+// End of the run set with Context::set_auto_stop_after_bars or Context::set_auto_stop_after.
+#[derive(Clone, Copy, Debug)]
+enum AutoStop {
+    Steps { end: u32, fade: u32 },
+    Time { end: Duration, fade: Duration },
 }
 
 /// An object of type [`Context`] is passed to the user [`Conductor`] at each clock tick through the
@@ -70,41 +266,260 @@ pub struct Context<T: MidiConnection> {
     pub(crate) clock: Clock,
     step: u32,
     running: bool,
-    on_pause: bool,
+    transport: TransportState,
     pause: bool,
+    handle: ContextHandle,
+    tempo_map: Option<TempoMap>,
+    pre_roll: u32,
+    // Send the clock while the transport is paused or stopped.
+    clock_while_stopped: bool,
+    resume_policy: ResumePolicy,
+    // Note pulses sent as auxiliary clocks: (channel, note, steps between two pulses).
+    pulse_clocks: Vec<(u8, MidiNote, u32)>,
+    // Pre-roll clock ticks remaining to be sent before the first step.
+    pre_roll_pending: u32,
+    resync: Option<Resync>,
+    // Bar of each locator.
+    locators: BTreeMap<String, u32>,
+    // Time of the last start, and number of steps played since then.
+    started_at: Option<Instant>,
+    steps_since_start: u32,
+    auto_stop: Option<AutoStop>,
+    params: Params,
+    // Maximum number of clock ticks of the run, used offline.
+    max_ticks: Option<u32>,
 }
 
 impl<T: MidiConnection> Context<T> {
-    /// Set the BPM (Beats per minute) of the sequencer.
-    pub fn set_bpm(&mut self, bpm: u8) {
-        self.clock.set_bpm(bpm);
+    pub(crate) fn new(midi: MidiController<T>) -> Self {
+        Self {
+            midi,
+            clock: Clock::new(DEFAULT_BPM),
+            step: 0,
+            running: true,
+            transport: TransportState::Stopped,
+            pause: false,
+            handle: ContextHandle::default(),
+            tempo_map: None,
+            pre_roll: 0,
+            clock_while_stopped: true,
+            resume_policy: ResumePolicy::default(),
+            pulse_clocks: vec![],
+            pre_roll_pending: 0,
+            resync: None,
+            locators: BTreeMap::new(),
+            started_at: None,
+            steps_since_start: 0,
+            auto_stop: None,
+            params: Params::default(),
+            max_ticks: None,
+        }
+    }
+
+    /// Set the BPM (Beats per minute) of the sequencer, e.g. 122.5. The BPM is clamped between
+    /// 1.0 and 1000.0, which allows very slow tempos as well as double-time clocks.
+    pub fn set_bpm(&mut self, bpm: f32) {
+        if bpm.is_nan() {
+            warn!("Invalid BPM, ignoring it");
+            return;
+        }
+        self.clock.set_bpm(bpm.clamp(MIN_BPM, MAX_BPM));
+    }
+
+    /// Swing the MIDI clock sent to the downstream devices: every second sixteenth note is delayed
+    /// by `swing` (from 0.0 to 0.5) times a sixteenth note, e.g. 0.33 for a triplet feel. As the
+    /// notes are played on the clock ticks, they are swung as well. Some devices don't handle an
+    /// irregular MIDI clock, set to 0.0 to send a regular clock again.
+    pub fn set_clock_swing(&mut self, swing: f32) {
+        let swing = swing.clamp(0.0, 0.5);
+        if swing > 0.0 {
+            warn!("Clock swing of {swing}, some devices may not follow an irregular MIDI clock");
+        }
+        self.clock.set_swing(swing);
+    }
+
+    /// Make the BPM follow `tempo_map` while the sequencer is playing. The tempo map overrides the
+    /// BPM set with [`Context::set_bpm`] whenever it has a point at or before the current step.
+    /// Set to `None` to remove the tempo map.
+    pub fn set_tempo_map(&mut self, tempo_map: Option<TempoMap>) {
+        self.tempo_map = tempo_map;
+    }
+
+    /// Retrieve the BPM (Beats per minute) of the sequencer.
+    pub fn get_bpm(&self) -> f32 {
+        self.clock.get_bpm()
+    }
+
+    /// Return a [`ContextHandle`] to set the BPM and control the transport from other threads.
+    pub fn handle(&self) -> ContextHandle {
+        self.handle.clone()
     }
 
-    /// Stop and exit the sequencer.
+    /// Stop and exit the sequencer. The sequencer exits at the end of the current clock tick with
+    /// the following sequence: all the notes are stopped, a last clock tick is emitted and a MIDI
+    /// stop message is sent if the transport was not already stopped. Calling this method several
+    /// times has the same effect as calling it once.
     pub fn quit(&mut self) {
         self.running = false
     }
 
     /// Pause the sequencer and send a MIDI stop message.
     pub fn pause(&mut self) {
-        self.on_pause = true;
+        self.transport = TransportState::Paused;
         self.pause = true;
         self.midi.stop_all_notes();
     }
 
-    /// Resume the sequencer and send a MIDI continue message.
+    /// Resume the sequencer and send a MIDI continue message, or restart it if the
+    /// [`ResumePolicy`] is [`ResumePolicy::Restart`].
     pub fn resume(&mut self) {
-        self.on_pause = false;
-        self.midi.send_continue();
+        match self.resume_policy {
+            ResumePolicy::Continue => {
+                self.transport = TransportState::Playing;
+                self.midi.send_continue();
+            }
+            ResumePolicy::Restart => self.start(),
+        }
+    }
+
+    /// Set the message sent by [`Context::resume`]. The default is [`ResumePolicy::Continue`].
+    pub fn set_resume_policy(&mut self, resume_policy: ResumePolicy) {
+        self.resume_policy = resume_policy;
     }
 
     /// Start the sequencer and send a MIDI start message. The current step is set to 0.
     pub fn start(&mut self) {
         self.step = 0;
-        self.on_pause = false;
+        self.pre_roll_pending = self.pre_roll;
+        self.started_at = Some(Instant::now());
+        self.steps_since_start = 0;
+        self.transport = TransportState::Playing;
+        self.midi.set_fade(1.0);
         self.midi.start();
     }
 
+    /// Resynchronize the devices that have drifted by sending a MIDI stop message, a MIDI song
+    /// position pointer message and a MIDI continue message over the next clock ticks, so that the
+    /// devices continue at the next sixteenth note, together with the sequencer. This has no effect
+    /// if the sequencer is not playing.
+    pub fn resync(&mut self) {
+        if self.transport == TransportState::Playing {
+            self.resync = Some(Resync::Requested);
+        }
+    }
+
+    /// Retrieve the time elapsed since the last call to [`Context::start`], pauses included.
+    pub fn elapsed(&self) -> Duration {
+        self.started_at.map_or(Duration::ZERO, |t| t.elapsed())
+    }
+
+    /// Retrieve the number of steps played since the last call to [`Context::start`]. Unlike
+    /// [`Context::get_step`], this is not changed by [`Context::jump_to`].
+    pub fn steps_since_start(&self) -> u32 {
+        self.steps_since_start
+    }
+
+    /// Quit the sequencer once `bars` bars have been played since the last call to
+    /// [`Context::start`]. During the last `fade_bars` bars, the notes are progressively muted,
+    /// on top of the density set with [`MidiController::set_density`]. This is useful for
+    /// installations that must end on schedule.
+    pub fn set_auto_stop_after_bars(&mut self, bars: u32, fade_bars: u32) {
+        self.midi.set_fade(1.0);
+        self.auto_stop = Some(AutoStop::Steps {
+            end: bars * BAR_LEN,
+            fade: fade_bars.min(bars) * BAR_LEN,
+        });
+    }
+
+    /// Quit the sequencer once `duration` has elapsed since the last call to [`Context::start`].
+    /// During the last `fade` of the run, the notes are progressively muted, on top of the density
+    /// set with [`MidiController::set_density`].
+    pub fn set_auto_stop_after(&mut self, duration: Duration, fade: Duration) {
+        self.midi.set_fade(1.0);
+        self.auto_stop = Some(AutoStop::Time {
+            end: duration,
+            fade: fade.min(duration),
+        });
+    }
+
+    /// Remove the auto stop set with [`Context::set_auto_stop_after_bars`] or
+    /// [`Context::set_auto_stop_after`].
+    pub fn clear_auto_stop(&mut self) {
+        self.midi.set_fade(1.0);
+        self.auto_stop = None;
+    }
+
+    /// Add a locator called `name` at the start of `bar` (starting at 0), e.g. "chorus 2", to jump
+    /// to it with [`Context::jump_to`]. If a locator with the same name exists, it is replaced.
+    pub fn add_locator(&mut self, name: &str, bar: u32) {
+        self.locators.insert(name.to_string(), bar);
+    }
+
+    /// Returns the name of the last locator at or before the current step, to display the
+    /// position in the arrangement.
+    pub fn current_locator(&self) -> Option<&str> {
+        self.locators
+            .iter()
+            .filter(|(_, &bar)| bar * BAR_LEN <= self.step)
+            .max_by_key(|(_, &bar)| bar)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Jump to the locator called `name`, see [`Context::add_locator`]. All the notes are stopped
+    /// and the current step is set to the start of the bar of the locator. If the sequencer is
+    /// playing, a MIDI stop message, a MIDI song position pointer message and a MIDI continue
+    /// message are sent so that the devices jump to the same position.
+    pub fn jump_to(&mut self, name: &str) {
+        let Some(&bar) = self.locators.get(name) else {
+            warn!("Locator {name} not found");
+            return;
+        };
+
+        self.midi.stop_all_notes();
+        self.step = bar * BAR_LEN;
+        self.midi.jump(self.step);
+        self.resync = None;
+        if self.transport == TransportState::Playing {
+            let position = self.step / SIXTEENTH_LEN;
+            self.midi.stop();
+            self.midi
+                .send_song_position(position.min(u16::MAX as u32) as u16);
+            self.midi.send_continue();
+        }
+    }
+
+    /// Send `ticks` additional MIDI clock ticks after each MIDI start message, before the notes of
+    /// the first step. Some devices need a few clock ticks to lock to the clock. The steps are not
+    /// shifted: the first step is still step 0. The default is 0.
+    pub fn set_pre_roll(&mut self, ticks: u32) {
+        self.pre_roll = ticks;
+    }
+
+    /// Keep sending the MIDI clock while the sequencer is paused or stopped if `enabled` is
+    /// `true`. Many devices need a continuous clock to stay locked to the tempo while stopped.
+    /// Set to `false` to only send the clock while the sequencer is playing. The default is
+    /// `true`.
+    pub fn set_clock_while_stopped(&mut self, enabled: bool) {
+        self.clock_while_stopped = enabled;
+    }
+
+    /// Add an auxiliary clock output sending `note` on the MIDI channel with `channel_id` at `ppqn`
+    /// pulses per quarter note while the sequencer is playing, e.g. 2 PPQN for a modular system
+    /// behind a MIDI to trigger interface. Each pulse lasts half of its period. `ppqn` is
+    /// rounded to a divider of 24, the resolution of the MIDI clock.
+    pub fn add_pulse_clock(&mut self, channel_id: u8, note: MidiNote, ppqn: u32) {
+        let div = (24 / ppqn.clamp(1, 24)).max(1);
+        if ppqn == 0 || 24 % ppqn != 0 {
+            warn!("Pulse clock of {ppqn} PPQN, using {} PPQN", 24 / div);
+        }
+        self.pulse_clocks.push((channel_id, note, div));
+    }
+
+    /// Remove all the auxiliary clock outputs, see [`Context::add_pulse_clock`].
+    pub fn clear_pulse_clocks(&mut self) {
+        self.pulse_clocks.clear();
+    }
+
     /// Retrieve the current MIDI step.
     /// - 96 steps make a bar
     /// - 24 steps make a whole note
@@ -114,15 +529,179 @@ impl<T: MidiConnection> Context<T> {
         self.step
     }
 
-    fn run(&mut self, mut conductor: impl Conductor) {
-        while self.running {
+    /// Access the parameter called `name`, e.g. `context.param("cutoff").set(0.7)`. The parameter
+    /// is created with the value 0.0 if it does not exist. See [`Params`].
+    pub fn param<'a>(&'a mut self, name: &'a str) -> Param<'a, T> {
+        Param::new(name, &mut self.params, &mut self.midi)
+    }
+
+    /// Access all the parameters, e.g. to save them or to find the parameters that changed.
+    pub fn params(&mut self) -> &mut Params {
+        &mut self.params
+    }
+
+    /// Set the values of the parameters saved in `params`, e.g. to restore them. The CC bindings
+    /// of the current parameters are kept and the CCs of the changed parameters are sent.
+    pub fn load_params(&mut self, params: Params) {
+        self.params.load(params, &mut self.midi);
+    }
+
+    /// Returns the notes that are currently sounding as `(channel_id, note, since_step)`, see
+    /// [`MidiController::sounding_notes`]. This allows to make decisions based on what is actually
+    /// playing, e.g. to avoid doubling a note.
+    pub fn active_notes(&self) -> impl Iterator<Item = (u8, MidiNote, u32)> + '_ {
+        self.midi.sounding_notes()
+    }
+
+    /// Run `job` on a new thread and return a [`Job`] handle to retrieve its result in a later
+    /// call to [`Conductor::update`] with [`Job::try_result`]. Use this to run expensive
+    /// computations (e.g., loading a big MIDI file) without blocking the midi clock.
+    pub fn spawn_job<R: Send + 'static>(
+        &mut self,
+        job: impl FnOnce() -> R + Send + 'static,
+    ) -> Job<R> {
+        Job::spawn(job)
+    }
+
+    /// Retrieve the state of the transport. This allows to distinguish a sequencer that has not
+    /// been started yet ([`TransportState::Stopped`]) from a sequencer that has been paused
+    /// ([`TransportState::Paused`]).
+    pub fn transport_state(&self) -> TransportState {
+        self.transport
+    }
+
+    /// Returns `true` if the sequencer has been paused with [`Context::pause`] and not resumed or
+    /// started since.
+    pub fn is_paused(&self) -> bool {
+        self.transport == TransportState::Paused
+    }
+
+    // Apply the requests sent through the handles.
+    pub(crate) fn poll_handle(&mut self) {
+        if let Some(bpm) = self.handle.take_bpm() {
+            self.set_bpm(bpm);
+        }
+        match self.handle.take_request() {
+            Some(HandleRequest::Start) => self.start(),
+            Some(HandleRequest::Pause) => self.pause(),
+            Some(HandleRequest::Resume) => self.resume(),
+            Some(HandleRequest::Quit) => self.quit(),
+            None => (),
+        }
+    }
+
+    // Send the messages of the resync in progress, the MIDI continue message has to be sent right
+    // before the clock tick of a sixteenth note.
+    fn update_resync(&mut self) {
+        if self.transport != TransportState::Playing {
+            self.resync = None;
+            return;
+        }
+        self.resync = match (self.resync, self.step % SIXTEENTH_LEN) {
+            (Some(Resync::Requested), 4) => {
+                self.midi.stop();
+                Some(Resync::Stopped)
+            }
+            (Some(Resync::Stopped), 5) => {
+                let position = (self.step + 1) / SIXTEENTH_LEN;
+                self.midi
+                    .send_song_position(position.min(u16::MAX as u32) as u16);
+                Some(Resync::Positioned)
+            }
+            (Some(Resync::Positioned), 0) => {
+                self.midi.send_continue();
+                None
+            }
+            (resync, _) => resync,
+        };
+    }
+
+    // Fade out and quit once the end set by the auto stop is reached.
+    pub(crate) fn update_auto_stop(&mut self) {
+        if self.transport != TransportState::Playing {
+            return;
+        }
+        let (position, end, fade) = match self.auto_stop {
+            Some(AutoStop::Steps { end, fade }) => {
+                (self.steps_since_start as f32, end as f32, fade as f32)
+            }
+            Some(AutoStop::Time { end, fade }) => (
+                self.elapsed().as_secs_f32(),
+                end.as_secs_f32(),
+                fade.as_secs_f32(),
+            ),
+            None => return,
+        };
+
+        if position >= end {
+            self.midi.set_fade(1.0);
+            self.quit();
+        } else if position > end - fade {
+            self.midi.set_fade((end - position) / fade);
+        }
+    }
+
+    // Send the pulses of the auxiliary clocks due at the current step.
+    pub(crate) fn update_pulse_clocks(&mut self) {
+        if self.transport != TransportState::Playing {
+            return;
+        }
+        for &(channel_id, note, div) in &self.pulse_clocks {
+            if self.step.is_multiple_of(div) {
+                self.midi.play_pulse(note, (div / 2).max(1), channel_id);
+            }
+        }
+    }
+
+    // Follow the tempo map at the current step.
+    pub(crate) fn update_tempo(&mut self) {
+        if self.transport != TransportState::Playing {
+            return;
+        }
+        if let Some(bpm) = self.tempo_map.as_ref().and_then(|m| m.bpm_at(self.step)) {
+            if bpm != self.get_bpm() {
+                self.set_bpm(bpm);
+            }
+        }
+    }
+
+    fn run(&mut self, mut conductor: impl Conductor) -> RunSummary {
+        let start_timestamp = Instant::now();
+        let mut ticks = 0;
+
+        // Arm phase: the time spent in Conductor::init is not caught up by the clock, and the
+        // messages sent in Conductor::init get a whole clock period before the first tick, which
+        // is sent at the end of the first iteration.
+        self.clock.reset();
+
+        while self.running && self.max_ticks.is_none_or(|m| ticks < m) {
+            self.poll_handle();
+            self.update_tempo();
+            self.update_auto_stop();
+            if self.transport == TransportState::Playing && self.step.is_multiple_of(BAR_LEN) {
+                conductor.update_bar(self);
+            }
             conductor.update(self);
+            self.update_pulse_clocks();
 
+            self.update_resync();
+            if self.transport == TransportState::Playing {
+                self.clock.sync_phase(self.step);
+            }
             self.clock.tick();
-            self.midi.send_clock();
+            if self.transport == TransportState::Playing || self.clock_while_stopped {
+                self.midi.send_clock();
+            }
+            ticks += 1;
 
-            if !self.on_pause {
+            if self.transport == TransportState::Playing {
+                for _ in 0..std::mem::take(&mut self.pre_roll_pending) {
+                    self.clock.tick();
+                    self.midi.send_clock();
+                    ticks += 1;
+                }
                 self.step += 1;
+                self.steps_since_start += 1;
                 self.midi.update(self.step);
             } else if self.pause {
                 self.midi.stop();
@@ -131,41 +710,54 @@ impl<T: MidiConnection> Context<T> {
         }
         self.midi.stop_all_notes();
         self.clock.tick();
-        self.midi.stop();
+        // If the sequencer was paused, the stop message has already been sent.
+        if self.transport == TransportState::Playing || self.pause {
+            self.midi.stop();
+            self.pause = false;
+        }
+        self.transport = TransportState::Stopped;
+
+        RunSummary {
+            ticks,
+            duration: start_timestamp.elapsed(),
+        }
     }
 }
 
+/// Summary of a run of the sequencer returned by [`run`] once the sequencer exits.
+#[derive(Clone, Copy, Debug)]
+pub struct RunSummary {
+    /// Number of MIDI clock ticks emitted.
+    pub ticks: u32,
+    /// Duration of the run, from the first clock tick to the exit of the sequencer.
+    pub duration: Duration,
+}
+
 /// `mseq` entry point. Run the sequencer by providing a conductor implementation. `port` is the
 /// MIDI port id used to send the midi messages. If set to `None`, information about the MIDI ports
-/// will be displayed and the output port will be asked to the user with a prompt.
-pub fn run(mut conductor: impl Conductor, port: Option<u32>) -> Result<(), MSeqError> {
+/// will be displayed and the output port will be asked to the user with a prompt. Once the
+/// sequencer exits (see [`Context::quit`]), a [`RunSummary`] is returned.
+pub fn run(conductor: impl Conductor, port: Option<u32>) -> Result<RunSummary, MSeqError> {
     let conn = MidirConnection::new(port)?;
-    let midi = MidiController::new(conn);
-
-    let mut ctx = Context {
-        midi,
-        clock: Clock::new(DEFAULT_BPM),
-        step: 0,
-        running: true,
-        on_pause: true,
-        pause: false,
-    };
+    Ok(run_with_connection(conductor, conn))
+}
 
+/// Run the sequencer like [`run`], but send the MIDI messages to `conn` instead of a MIDI output
+/// port. [`NullConnection`] and [`TextConnection`] allow to run a conductor on a machine with no
+/// MIDI device.
+pub fn run_with_connection<T: MidiConnection>(
+    mut conductor: impl Conductor,
+    conn: T,
+) -> RunSummary {
+    let mut ctx = Context::new(MidiController::new(conn));
     conductor.init(&mut ctx);
-    ctx.run(conductor);
-
-    Ok(())
+    info!("Random seed: {}", ctx.midi.seed());
+    ctx.run(conductor)
 }
 
 /// Perform a linear conversion from `[0.0, 1.0]` to [0, 127]. If `v` is smaller than `0.0` return
 /// 0. If `v` is greater than `1.0` return 127. The main purpose of this function is to be used with
 /// MIDI control changes (CC).
 pub fn param_value(v: f32) -> u8 {
-    if v < -1.0 {
-        return 0;
-    }
-    if v > 1.0 {
-        return 127;
-    }
-    63 + (v * 63.0).round() as u8
+    (v.clamp(0.0, 1.0) * 127.0).round() as u8
 }
@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::track::TrackError;
+use crate::{DeteTrack, MSeqError, Note};
+
+// Extension of the files indexed by the library.
+const MIDI_EXTENSION: &str = "mid";
+
+/// Library of the midi files of a directory tree, loaded as [`DeteTrack`] on first use. The most
+/// recently used tracks are kept in a cache, so large collections don't have to be loaded at
+/// startup nor kept in memory.
+pub struct TrackLibrary {
+    // Path of each midi file, indexed by its name: its path relative to the root directory
+    // without the extension (e.g., "drums/break_1").
+    paths: BTreeMap<String, PathBuf>,
+    capacity: usize,
+    // Loaded tracks, the most recently used last.
+    cache: Vec<(String, DeteTrack)>,
+}
+
+impl TrackLibrary {
+    /// Index the midi files (`.mid`) of the directory `dir` and of its subdirectories. At most
+    /// `capacity` tracks are kept in the cache.
+    pub fn new<P: AsRef<Path>>(dir: P, capacity: usize) -> Result<Self, MSeqError> {
+        let mut paths = BTreeMap::new();
+        index_dir(dir.as_ref(), dir.as_ref(), &mut paths).map_err(|e| MSeqError::Track {
+            path: dir.as_ref().to_path_buf(),
+            source: TrackError::Io(e),
+        })?;
+        Ok(Self {
+            paths,
+            capacity: capacity.max(1),
+            cache: vec![],
+        })
+    }
+
+    /// Names of all the tracks of the library, in alphabetical order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.paths.keys().map(|n| n.as_str())
+    }
+
+    /// Names of the tracks containing `query` (case insensitive), in alphabetical order.
+    pub fn search(&self, query: &str) -> Vec<&str> {
+        let query = query.to_lowercase();
+        self.names()
+            .filter(|n| n.to_lowercase().contains(&query))
+            .collect()
+    }
+
+    /// Returns the track called `name`, loaded with [`DeteTrack::load_from_file`] if it is not in
+    /// the cache. Provide the root note of the track to allow for transposition. channel_id is the
+    /// midi channel where this track will be played when passed to the MidiController.
+    pub fn load(&mut self, name: &str, root: Note, channel_id: u8) -> Result<DeteTrack, MSeqError> {
+        let mut track = match self.cache.iter().position(|(n, _)| n == name) {
+            Some(i) => {
+                let entry = self.cache.remove(i);
+                let track = entry.1.clone();
+                self.cache.push(entry);
+                track
+            }
+            None => {
+                let path = self.paths.get(name).ok_or_else(|| MSeqError::Library {
+                    name: name.to_string(),
+                })?;
+                let track = DeteTrack::load_from_file(path, root, channel_id, name)?;
+                if self.cache.len() >= self.capacity {
+                    self.cache.remove(0);
+                }
+                self.cache.push((name.to_string(), track.clone()));
+                track
+            }
+        };
+        track.root = root;
+        track.channel_id = channel_id;
+        Ok(track)
+    }
+
+    /// Returns `true` if the track called `name` is in the cache.
+    pub fn is_loaded(&self, name: &str) -> bool {
+        self.cache.iter().any(|(n, _)| n == name)
+    }
+}
+
+fn index_dir(
+    root: &Path,
+    dir: &Path,
+    paths: &mut BTreeMap<String, PathBuf>,
+) -> std::io::Result<()> {
+    for entry in fs_err::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            index_dir(root, &path, paths)?;
+        } else if path.extension().is_some_and(|e| e == MIDI_EXTENSION) {
+            let name = path.strip_prefix(root).unwrap_or(&path).with_extension("");
+            let name = name
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            paths.insert(name, path);
+        }
+    }
+    Ok(())
+}
@@ -1,8 +1,12 @@
+use crate::MidiNote;
 use midir::MidiOutput;
+#[cfg(feature = "promptly")]
 use promptly::{prompt_default, ReadlineError};
+use std::io::Write;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum MidiError {
     #[error("Init error: {0}")]
     Init(#[from] midir::InitError),
@@ -10,24 +14,39 @@ pub enum MidiError {
     Connect(#[from] midir::ConnectError<MidiOutput>),
     #[error("Send error: {0}")]
     Send(#[from] midir::SendError),
-    #[error("Read line [{}: {}]", file!(), line!())]
+    #[cfg(feature = "promptly")]
+    #[error("Read line error: {0}")]
     ReadLine(#[from] ReadlineError),
     #[error("Invalid port number selected")]
     PortNumber(),
+    #[cfg(not(feature = "promptly"))]
+    #[error("Interactive port selection requires the `promptly` feature")]
+    NoPrompt(),
     #[error("No midi output found")]
     NoOutput(),
+    #[error("Write error: {0}")]
+    Write(#[from] std::io::Error),
 }
 
 const CLOCK: u8 = 0xf8;
 const START: u8 = 0xfa;
 const CONTINUE: u8 = 0xfb;
 const STOP: u8 = 0xfc;
+const SONG_POSITION: u8 = 0xf2;
 const NOTE_ON: u8 = 0x90;
 const NOTE_OFF: u8 = 0x80;
 const CC: u8 = 0xB0;
+const PC: u8 = 0xC0;
+const PITCH_BEND: u8 = 0xE0;
+const CHANNEL_PRESSURE: u8 = 0xD0;
+const POLY_PRESSURE: u8 = 0xA0;
+const SYSEX_START: u8 = 0xF0;
+const SYSEX_END: u8 = 0xF7;
 
-/// This trait should not be implemented in the user code. The purpose of this trait is be able to reuse
-/// the same code with different midi API, using static dispatch.
+/// The purpose of this trait is be able to reuse the same code with different midi API, using
+/// static dispatch. It can be implemented in the user code to pass a custom connection to
+/// [`crate::run_with_connection`]. The messages added after the first ones have a default
+/// implementation that ignores them, so that such connections keep compiling.
 pub trait MidiConnection {
     #[doc(hidden)]
     fn send_start(&mut self) -> Result<(), MidiError>;
@@ -38,15 +57,54 @@ pub trait MidiConnection {
     #[doc(hidden)]
     fn send_clock(&mut self) -> Result<(), MidiError>;
     #[doc(hidden)]
+    fn send_song_position(&mut self, _position: u16) -> Result<(), MidiError> {
+        Ok(())
+    }
+    #[doc(hidden)]
     fn send_note_on(&mut self, channel_id: u8, note: u8, velocity: u8) -> Result<(), MidiError>;
     #[doc(hidden)]
     fn send_note_off(&mut self, channel_id: u8, note: u8) -> Result<(), MidiError>;
     #[doc(hidden)]
     fn send_cc(&mut self, channel_id: u8, parameter: u8, value: u8) -> Result<(), MidiError>;
+    #[doc(hidden)]
+    fn send_program_change(&mut self, channel_id: u8, program: u8) -> Result<(), MidiError>;
+    #[doc(hidden)]
+    fn send_pitch_bend(&mut self, _channel_id: u8, _value: u16) -> Result<(), MidiError> {
+        Ok(())
+    }
+    #[doc(hidden)]
+    fn send_channel_pressure(&mut self, _channel_id: u8, _pressure: u8) -> Result<(), MidiError> {
+        Ok(())
+    }
+    #[doc(hidden)]
+    fn send_poly_pressure(
+        &mut self,
+        _channel_id: u8,
+        _note: u8,
+        _pressure: u8,
+    ) -> Result<(), MidiError> {
+        Ok(())
+    }
+    #[doc(hidden)]
+    fn send_sysex(&mut self, _data: &[u8]) -> Result<(), MidiError> {
+        Ok(())
+    }
 }
 
 pub struct MidirConnection(midir::MidiOutputConnection);
 
+// Ask the user to select one of the output ports.
+#[cfg(feature = "promptly")]
+fn prompt_port() -> Result<usize, MidiError> {
+    Ok(prompt_default("Select output port", 0)?)
+}
+
+// Without the prompt, the output port has to be given to crate::run.
+#[cfg(not(feature = "promptly"))]
+fn prompt_port() -> Result<usize, MidiError> {
+    Err(MidiError::NoPrompt())
+}
+
 impl MidirConnection {
     pub(crate) fn new(port: Option<u32>) -> Result<Self, MidiError> {
         let midi_out = MidiOutput::new("out")?;
@@ -73,8 +131,7 @@ impl MidirConnection {
                         println!("{}: {}", i, midi_out.port_name(p).unwrap());
                     }
 
-                    let port_number: usize = prompt_default("Select output port", 0)?;
-                    match out_ports.get(port_number) {
+                    match out_ports.get(prompt_port()?) {
                         None => return Err(MidiError::PortNumber()),
                         Some(x) => x,
                     }
@@ -108,6 +165,15 @@ impl MidiConnection for MidirConnection {
         Ok(())
     }
 
+    fn send_song_position(&mut self, position: u16) -> Result<(), MidiError> {
+        self.0.send(&[
+            SONG_POSITION,
+            (position & 0x7f) as u8,
+            ((position >> 7) & 0x7f) as u8,
+        ])?;
+        Ok(())
+    }
+
     fn send_note_on(&mut self, channel_id: u8, note: u8, velocity: u8) -> Result<(), MidiError> {
         self.0.send(&[NOTE_ON | channel_id, note, velocity])?;
         Ok(())
@@ -122,4 +188,228 @@ impl MidiConnection for MidirConnection {
         self.0.send(&[CC | channel_id, parameter, value])?;
         Ok(())
     }
+
+    fn send_program_change(&mut self, channel_id: u8, program: u8) -> Result<(), MidiError> {
+        self.0.send(&[PC | channel_id, program])?;
+        Ok(())
+    }
+
+    fn send_pitch_bend(&mut self, channel_id: u8, value: u16) -> Result<(), MidiError> {
+        self.0.send(&[
+            PITCH_BEND | channel_id,
+            (value & 0x7f) as u8,
+            ((value >> 7) & 0x7f) as u8,
+        ])?;
+        Ok(())
+    }
+
+    fn send_channel_pressure(&mut self, channel_id: u8, pressure: u8) -> Result<(), MidiError> {
+        self.0.send(&[CHANNEL_PRESSURE | channel_id, pressure])?;
+        Ok(())
+    }
+
+    fn send_poly_pressure(
+        &mut self,
+        channel_id: u8,
+        note: u8,
+        pressure: u8,
+    ) -> Result<(), MidiError> {
+        self.0.send(&[POLY_PRESSURE | channel_id, note, pressure])?;
+        Ok(())
+    }
+
+    fn send_sysex(&mut self, data: &[u8]) -> Result<(), MidiError> {
+        let mut message = Vec::with_capacity(data.len() + 2);
+        message.push(SYSEX_START);
+        message.extend_from_slice(data);
+        message.push(SYSEX_END);
+        self.0.send(&message)?;
+        Ok(())
+    }
+}
+
+/// [`MidiConnection`] that discards every MIDI message. Use it with [`crate::run_with_connection`]
+/// to run a conductor without any MIDI device.
+#[derive(Default)]
+pub struct NullConnection;
+
+impl MidiConnection for NullConnection {
+    fn send_start(&mut self) -> Result<(), MidiError> {
+        Ok(())
+    }
+
+    fn send_continue(&mut self) -> Result<(), MidiError> {
+        Ok(())
+    }
+
+    fn send_stop(&mut self) -> Result<(), MidiError> {
+        Ok(())
+    }
+
+    fn send_clock(&mut self) -> Result<(), MidiError> {
+        Ok(())
+    }
+
+    fn send_song_position(&mut self, _position: u16) -> Result<(), MidiError> {
+        Ok(())
+    }
+
+    fn send_note_on(&mut self, _channel_id: u8, _note: u8, _velocity: u8) -> Result<(), MidiError> {
+        Ok(())
+    }
+
+    fn send_note_off(&mut self, _channel_id: u8, _note: u8) -> Result<(), MidiError> {
+        Ok(())
+    }
+
+    fn send_cc(&mut self, _channel_id: u8, _parameter: u8, _value: u8) -> Result<(), MidiError> {
+        Ok(())
+    }
+
+    fn send_program_change(&mut self, _channel_id: u8, _program: u8) -> Result<(), MidiError> {
+        Ok(())
+    }
+
+    fn send_pitch_bend(&mut self, _channel_id: u8, _value: u16) -> Result<(), MidiError> {
+        Ok(())
+    }
+
+    fn send_channel_pressure(&mut self, _channel_id: u8, _pressure: u8) -> Result<(), MidiError> {
+        Ok(())
+    }
+
+    fn send_poly_pressure(
+        &mut self,
+        _channel_id: u8,
+        _note: u8,
+        _pressure: u8,
+    ) -> Result<(), MidiError> {
+        Ok(())
+    }
+
+    fn send_sysex(&mut self, _data: &[u8]) -> Result<(), MidiError> {
+        Ok(())
+    }
+}
+
+/// [`MidiConnection`] that writes every MIDI message in a human-readable form, one message per
+/// line, to any [`Write`] (e.g., [`std::io::stdout`]). Use it with [`crate::run_with_connection`]
+/// to develop and demo a conductor on a machine with no MIDI device.
+pub struct TextConnection<W: Write> {
+    writer: W,
+    print_clock: bool,
+}
+
+impl<W: Write> TextConnection<W> {
+    /// Create a new [`TextConnection`] writing to `writer`. MIDI clock messages are only written
+    /// if `print_clock` is `true` since they are sent 24 times per beat.
+    pub fn new(writer: W, print_clock: bool) -> Self {
+        Self {
+            writer,
+            print_clock,
+        }
+    }
+
+    fn print_note(&mut self, message: &str, channel_id: u8, note: u8) -> Result<(), MidiError> {
+        let midi_note = MidiNote::from_midi_value(note, 0);
+        write!(
+            self.writer,
+            "{message}\tchannel: {channel_id}\tnote: {}{}",
+            midi_note.note, midi_note.octave
+        )?;
+        Ok(())
+    }
+}
+
+impl<W: Write> MidiConnection for TextConnection<W> {
+    fn send_start(&mut self) -> Result<(), MidiError> {
+        writeln!(self.writer, "Start")?;
+        Ok(())
+    }
+
+    fn send_continue(&mut self) -> Result<(), MidiError> {
+        writeln!(self.writer, "Continue")?;
+        Ok(())
+    }
+
+    fn send_stop(&mut self) -> Result<(), MidiError> {
+        writeln!(self.writer, "Stop")?;
+        Ok(())
+    }
+
+    fn send_clock(&mut self) -> Result<(), MidiError> {
+        if self.print_clock {
+            writeln!(self.writer, "Clock")?;
+        }
+        Ok(())
+    }
+
+    fn send_song_position(&mut self, position: u16) -> Result<(), MidiError> {
+        writeln!(self.writer, "Song position\tposition: {position}")?;
+        Ok(())
+    }
+
+    fn send_note_on(&mut self, channel_id: u8, note: u8, velocity: u8) -> Result<(), MidiError> {
+        self.print_note("Note on", channel_id, note)?;
+        writeln!(self.writer, "\tvelocity: {velocity}")?;
+        Ok(())
+    }
+
+    fn send_note_off(&mut self, channel_id: u8, note: u8) -> Result<(), MidiError> {
+        self.print_note("Note off", channel_id, note)?;
+        writeln!(self.writer)?;
+        Ok(())
+    }
+
+    fn send_cc(&mut self, channel_id: u8, parameter: u8, value: u8) -> Result<(), MidiError> {
+        writeln!(
+            self.writer,
+            "CC\tchannel: {channel_id}\tparameter: {parameter}\tvalue: {value}"
+        )?;
+        Ok(())
+    }
+
+    fn send_program_change(&mut self, channel_id: u8, program: u8) -> Result<(), MidiError> {
+        writeln!(
+            self.writer,
+            "Program change\tchannel: {channel_id}\tprogram: {program}"
+        )?;
+        Ok(())
+    }
+
+    fn send_pitch_bend(&mut self, channel_id: u8, value: u16) -> Result<(), MidiError> {
+        writeln!(
+            self.writer,
+            "Pitch bend\tchannel: {channel_id}\tvalue: {value}"
+        )?;
+        Ok(())
+    }
+
+    fn send_channel_pressure(&mut self, channel_id: u8, pressure: u8) -> Result<(), MidiError> {
+        writeln!(
+            self.writer,
+            "Channel pressure\tchannel: {channel_id}\tpressure: {pressure}"
+        )?;
+        Ok(())
+    }
+
+    fn send_poly_pressure(
+        &mut self,
+        channel_id: u8,
+        note: u8,
+        pressure: u8,
+    ) -> Result<(), MidiError> {
+        self.print_note("Poly pressure", channel_id, note)?;
+        writeln!(self.writer, "\tpressure: {pressure}")?;
+        Ok(())
+    }
+
+    fn send_sysex(&mut self, data: &[u8]) -> Result<(), MidiError> {
+        write!(self.writer, "SysEx\tdata:")?;
+        for byte in data {
+            write!(self.writer, " {byte:02x}")?;
+        }
+        writeln!(self.writer)?;
+        Ok(())
+    }
 }
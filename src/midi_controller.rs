@@ -1,11 +1,26 @@
 use crate::midi_connection::MidiConnection;
 use crate::note::Note;
 use crate::Track;
-use log::error;
-use std::collections::{HashMap, HashSet};
+use log::{error, warn};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
 use std::hash::Hash;
 
-const MAX_MIDI_CHANNEL: u8 = 16;
+pub(crate) const MAX_MIDI_CHANNEL: u8 = 16;
+const BANK_SELECT_MSB: u8 = 0;
+const BANK_SELECT_LSB: u8 = 32;
+const DATA_ENTRY_MSB: u8 = 6;
+const DATA_ENTRY_LSB: u8 = 38;
+const NRPN_LSB: u8 = 98;
+const NRPN_MSB: u8 = 99;
+const RPN_LSB: u8 = 100;
+const RPN_MSB: u8 = 101;
+// RPN selecting no parameter, sent after a data entry to protect the parameter.
+const RPN_NULL: u16 = 0x3fff;
+// RPN of the MPE Configuration Message.
+const RPN_MPE_CONFIGURATION: u16 = 6;
 
 /// Note that can be sent through a MIDI message.
 #[derive(Default, Clone, Copy, serde::Deserialize, PartialEq, Eq, Debug)]
@@ -61,30 +76,243 @@ impl Hash for NotePlay {
     }
 }
 
+// Note off scheduled by play_note. The notes are ordered by step, then in the order in which they
+// were scheduled.
+struct NoteOff {
+    step: u32,
+    order: u64,
+    note_play: NotePlay,
+}
+
+impl PartialEq for NoteOff {
+    fn eq(&self, other: &Self) -> bool {
+        (self.step, self.order) == (other.step, other.order)
+    }
+}
+
+impl Eq for NoteOff {}
+
+impl PartialOrd for NoteOff {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for NoteOff {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.step, self.order).cmp(&(other.step, other.order))
+    }
+}
+
+// Last bank and program sent on a MIDI channel.
+#[derive(Default, Clone, Copy)]
+struct ProgramState {
+    // (MSB, LSB)
+    bank: Option<(u8, u8)>,
+    program: Option<u8>,
+}
+
+// Note for which a note on message has been sent, and no note off message yet.
+#[derive(Clone, Copy)]
+struct SoundingNote {
+    midi_note: MidiNote,
+    // Step at which the note on message was sent.
+    since: u32,
+    // The note has been reported as stuck.
+    reported: bool,
+}
+
+// Interpolation of a CC value over several steps.
+struct CcMorph {
+    channel_id: u8,
+    parameter: u8,
+    from: u8,
+    to: u8,
+    start_step: u32,
+    len: u32,
+}
+
+/// Order in which the note off and note on messages that happen at the same step are sent, see
+/// [`MidiController::set_note_order`].
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NoteOrder {
+    /// Send the note off messages first. Notes ending at a step never overlap with notes starting
+    /// at the same step.
+    #[default]
+    OffFirst,
+    /// Send the note on messages first. Notes ending at a step overlap with notes starting at the
+    /// same step, which triggers legato on most monophonic synthesizers.
+    OnFirst,
+}
+
+/// Behavior when a note ends and starts again (same note and same MIDI channel) at the same step,
+/// see [`MidiController::set_retrigger_policy`].
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RetriggerPolicy {
+    /// Send the note off message followed by the note on message, regardless of the
+    /// [`NoteOrder`].
+    #[default]
+    Retrigger,
+    /// Send neither the note off message nor the note on message. The note keeps playing with its
+    /// original velocity until the end of the new note.
+    Tie,
+}
+
+/// Behavior when a note is outside the range of its MIDI channel, see
+/// [`MidiController::set_note_range`].
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RangePolicy {
+    /// Replace the note by the lowest or highest note of the range.
+    #[default]
+    Clamp,
+    /// Transpose the note by octaves until it is in the range. If the range is smaller than an
+    /// octave and no octave fits, the note is clamped.
+    Fold,
+    /// Do not play the note.
+    Drop,
+}
+
+/// MPE (MIDI Polyphonic Expression) zone, see [`MidiController::set_mpe_zone`].
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum MpeZone {
+    /// Master channel 0, member channels starting from channel 1.
+    Lower,
+    /// Master channel 15, member channels starting from channel 14 downwards.
+    Upper,
+}
+
+impl MpeZone {
+    fn master_channel(self) -> u8 {
+        match self {
+            MpeZone::Lower => 0,
+            MpeZone::Upper => MAX_MIDI_CHANNEL - 1,
+        }
+    }
+
+    fn member_channel(self, index: u8) -> u8 {
+        match self {
+            MpeZone::Lower => 1 + index,
+            MpeZone::Upper => MAX_MIDI_CHANNEL - 2 - index,
+        }
+    }
+}
+
 /// The [`MidiController`] provides a MIDI interface to the user.
+///
+/// The MIDI messages of a step are sent in a deterministic order:
+/// - CC, bank select and program change messages are sent immediately when requested, hence
+///   before the notes of the same step.
+/// - Note off and note on messages are sent at the beginning of the next MIDI clock cycle,
+///   following [`NoteOrder`]. Within the note offs (resp. note ons) the messages are sent in the
+///   order in which the notes were requested.
+/// - When the sequencer is paused or quit, the notes are stopped ordered by MIDI channel and
+///   note.
 pub struct MidiController<T: MidiConnection> {
     step: u32,
 
-    // Every note currently being played triggered by play_note. The key is the step at which to
-    // stop the note.
-    play_note_set: HashMap<u32, Vec<NotePlay>>,
+    // Every note currently being played triggered by play_note, as a min-heap of note offs.
+    play_note_set: BinaryHeap<Reverse<NoteOff>>,
+    // Number of note offs scheduled, to order the note offs of the same step.
+    note_off_count: u64,
 
-    // Every note currently being played triggered by start_note.
-    start_note_set: HashSet<NotePlay>,
+    // Every note currently being played triggered by start_note, with the step at which it
+    // started.
+    start_note_set: HashMap<NotePlay, u32>,
 
     // Notes to play at the next update call
     notes_to_play: Vec<NotePlay>,
 
+    // Notes scheduled by play_note_at. The key is the step at which to play the notes, the value
+    // contains the length of the notes.
+    scheduled_notes: HashMap<u32, Vec<(NotePlay, u32)>>,
+
+    // Random number generator shared by the tracks, and the seed used to initialize it.
+    rng: StdRng,
+    seed: u64,
+
+    // Last bank and program sent on each MIDI channel.
+    programs: BTreeMap<u8, ProgramState>,
+
+    // Last value sent for each (channel, CC parameter).
+    cc_values: BTreeMap<(u8, u8), u8>,
+
+    // Named snapshots of cc_values.
+    cc_snapshots: HashMap<String, BTreeMap<(u8, u8), u8>>,
+
+    // CC interpolations in progress.
+    cc_morphs: Vec<CcMorph>,
+
+    // (channel, CC parameter) sent instead of each mapped (channel, CC parameter).
+    cc_mappings: BTreeMap<(u8, u8), Vec<(u8, u8)>>,
+
+    note_order: NoteOrder,
+    retrigger_policy: RetriggerPolicy,
+
+    // MIDI channels whose notes must not be transposed.
+    drum_channels: HashSet<u8>,
+
+    // Probability to play a note, globally and on each MIDI channel.
+    density: f32,
+    channel_densities: BTreeMap<u8, f32>,
+    // Fade out of the auto stop, multiplied by the densities.
+    fade: f32,
+
+    // Maximum length of the notes on each MIDI channel.
+    max_gates: BTreeMap<u8, u32>,
+
+    // Lowest and highest MIDI values of the notes on each MIDI channel.
+    note_ranges: BTreeMap<u8, (u8, u8, RangePolicy)>,
+
+    // Velocity replacing the velocity of the notes, and lowest and highest velocities on each
+    // MIDI channel.
+    fixed_velocities: BTreeMap<u8, u8>,
+    velocity_ranges: BTreeMap<u8, (u8, u8)>,
+
+    // Number of member channels of each MPE zone, and index of the next member channel to use.
+    mpe_zones: BTreeMap<MpeZone, (u8, u8)>,
+
+    // Notes currently sounding, indexed by (channel, MIDI value).
+    sounding_notes: BTreeMap<(u8, u8), SoundingNote>,
+
+    // Number of steps after which a sounding note is reported as stuck, and whether it is then
+    // released.
+    stuck_note_threshold: Option<u32>,
+    release_stuck_notes: bool,
+
     conn: T,
 }
 
 impl<T: MidiConnection> MidiController<T> {
     pub(crate) fn new(conn: T) -> Self {
+        let seed = rand::random();
         Self {
             step: 0,
-            play_note_set: HashMap::new(),
-            start_note_set: HashSet::new(),
+            play_note_set: BinaryHeap::new(),
+            note_off_count: 0,
+            start_note_set: HashMap::new(),
             notes_to_play: vec![],
+            scheduled_notes: HashMap::new(),
+            rng: StdRng::seed_from_u64(seed),
+            seed,
+            programs: BTreeMap::new(),
+            cc_values: BTreeMap::new(),
+            cc_snapshots: HashMap::new(),
+            cc_morphs: vec![],
+            cc_mappings: BTreeMap::new(),
+            note_order: NoteOrder::default(),
+            retrigger_policy: RetriggerPolicy::default(),
+            drum_channels: HashSet::new(),
+            density: 1.0,
+            channel_densities: BTreeMap::new(),
+            fade: 1.0,
+            max_gates: BTreeMap::new(),
+            note_ranges: BTreeMap::new(),
+            fixed_velocities: BTreeMap::new(),
+            velocity_ranges: BTreeMap::new(),
+            mpe_zones: BTreeMap::new(),
+            sounding_notes: BTreeMap::new(),
+            stuck_note_threshold: None,
+            release_stuck_notes: false,
             conn,
         }
     }
@@ -95,13 +323,49 @@ impl<T: MidiConnection> MidiController<T> {
         track.play_step(self.step, self);
     }
 
+    /// Random number generator that should be used by the tracks and the conductor to make
+    /// random choices (e.g., [`DeteTrack::add_alternatives`]).
+    ///
+    /// [`DeteTrack::add_alternatives`]: crate::DeteTrack::add_alternatives
+    pub fn rng(&mut self) -> &mut StdRng {
+        &mut self.rng
+    }
+
+    /// Seed the random number generator returned by [`MidiController::rng`]. By default, the seed
+    /// is chosen randomly and printed when the sequencer starts. Setting the same seed in
+    /// [`crate::Conductor::init`] reproduces all the random choices of a previous run.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Returns the seed of the random number generator, see [`MidiController::set_seed`].
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
     /// Request the MIDI controller to play a note at the current MIDI step. Specify the length
     /// (`len`) of the note and the MIDI channel id (`channel_id`) on which to send the note.
     pub fn play_note(&mut self, midi_note: MidiNote, len: u32, channel_id: u8) {
-        if len == 0 {
+        let Some(midi_note) = self.output_note(midi_note, channel_id) else {
+            return;
+        };
+        if len == 0 || !self.pass_density(channel_id) {
             return;
         }
 
+        let len = self.gate(channel_id, len);
+        let note_play = NotePlay {
+            midi_note,
+            channel_id,
+        };
+        self.notes_to_play.push(note_play);
+        self.stop_note_at_step(note_play, self.step + len);
+    }
+
+    // Play a note that is not subject to the density nor to the range and velocity settings of
+    // the channel, e.g. a clock pulse.
+    pub(crate) fn play_pulse(&mut self, midi_note: MidiNote, len: u32, channel_id: u8) {
         let note_play = NotePlay {
             midi_note,
             channel_id,
@@ -110,41 +374,663 @@ impl<T: MidiConnection> MidiController<T> {
         self.stop_note_at_step(note_play, self.step + len);
     }
 
+    /// Request the MIDI controller to play a note `offset` steps after the current MIDI step.
+    /// Specify the length (`len`) of the note and the MIDI channel id (`channel_id`) on which to
+    /// send the note. This allows to schedule notes in advance, e.g. a whole bar in
+    /// [`crate::Conductor::update_bar`]. The scheduled notes are discarded if the sequencer is
+    /// paused, started or quit before they are played.
+    pub fn play_note_at(&mut self, midi_note: MidiNote, offset: u32, len: u32, channel_id: u8) {
+        if offset == 0 {
+            self.play_note(midi_note, len, channel_id);
+            return;
+        }
+        let Some(midi_note) = self.output_note(midi_note, channel_id) else {
+            return;
+        };
+        if len == 0 || !self.pass_density(channel_id) {
+            return;
+        }
+
+        let len = self.gate(channel_id, len);
+        let note_play = NotePlay {
+            midi_note,
+            channel_id,
+        };
+        self.scheduled_notes
+            .entry(self.step + offset)
+            .or_default()
+            .push((note_play, len));
+    }
+
     /// Request the MIDI controller to start playing a note. Specify the MIDI channel id
     /// (`channel_id`). The note will not stop until [`MidiController::stop_note`] is called with
     /// the same note, ocatve and MIDI channel id.
     pub fn start_note(&mut self, midi_note: MidiNote, channel_id: u8) {
+        let Some(midi_note) = self.output_note(midi_note, channel_id) else {
+            return;
+        };
         let note_play = NotePlay {
             midi_note,
             channel_id,
         };
         self.notes_to_play.push(note_play);
-        self.start_note_set.insert(note_play);
+        self.start_note_set.insert(note_play, self.step);
     }
 
     /// Request the MIDI controller to stop playing a note that was started by
     /// [`MidiController::start_note`]. The note will stop only if the note, ocatave and MIDI
     /// channel are identical to what was used in [`MidiController::start_note`].
     pub fn stop_note(&mut self, midi_note: MidiNote, channel_id: u8) {
+        // The note was started with the same range applied.
+        let Some(midi_note) = self.output_note(midi_note, channel_id) else {
+            return;
+        };
         let note_play = NotePlay {
             midi_note,
             channel_id,
         };
+        let same_note = |n: &NotePlay| {
+            n.channel_id == channel_id && n.midi_note.midi_value() == midi_note.midi_value()
+        };
+
+        // A note started at this step has not been sent yet, it must not be played at all. Only
+        // the note requested by start_note is dropped, the same note can also be played by
+        // play_note at this step.
+        let mut started_now = false;
+        self.start_note_set.retain(|n, start| {
+            started_now |= same_note(n) && *start == self.step;
+            !same_note(n)
+        });
+        if started_now {
+            if let Some(i) = self.notes_to_play.iter().position(same_note) {
+                self.notes_to_play.remove(i);
+            }
+            return;
+        }
         self.stop_note_at_step(note_play, self.step);
     }
 
     fn stop_note_at_step(&mut self, note_play: NotePlay, step: u32) {
-        self.play_note_set.entry(step).or_default().push(note_play);
+        self.play_note_set.push(Reverse(NoteOff {
+            step,
+            order: self.note_off_count,
+            note_play,
+        }));
+        self.note_off_count += 1;
+    }
+
+    /// Set the order in which the note off and note on messages that happen at the same step are
+    /// sent. The default is [`NoteOrder::OffFirst`].
+    pub fn set_note_order(&mut self, note_order: NoteOrder) {
+        self.note_order = note_order;
+    }
+
+    /// Set the behavior when a note ends and starts again (same note and same MIDI channel) at
+    /// the same step. The default is [`RetriggerPolicy::Retrigger`].
+    pub fn set_retrigger_policy(&mut self, retrigger_policy: RetriggerPolicy) {
+        self.retrigger_policy = retrigger_policy;
+    }
+
+    /// Set the global density (from 0.0 to 1.0): the probability for each note requested with
+    /// [`MidiController::play_note`] or [`MidiController::play_note_at`] to be played. This allows
+    /// to thin out or fill in the whole arrangement with a single parameter, e.g. a CC of a MIDI
+    /// controller. The default is 1.0, all the notes are played.
+    pub fn set_density(&mut self, density: f32) {
+        self.density = density.clamp(0.0, 1.0);
+    }
+
+    /// Set the density of the MIDI channel with `channel_id`, or remove it with `None`. The
+    /// density of the channel is multiplied by the global density, see
+    /// [`MidiController::set_density`].
+    pub fn set_channel_density(&mut self, channel_id: u8, density: Option<f32>) {
+        match density {
+            Some(density) => self
+                .channel_densities
+                .insert(channel_id, density.clamp(0.0, 1.0)),
+            None => self.channel_densities.remove(&channel_id),
+        };
+    }
+
+    // Set the fade out factor (from 0.0 to 1.0) of the auto stop, which is applied on top of the
+    // densities so that the density set by the user is kept.
+    pub(crate) fn set_fade(&mut self, fade: f32) {
+        self.fade = fade.clamp(0.0, 1.0);
+    }
+
+    // Randomly decide if a note is played on channel_id according to the densities.
+    fn pass_density(&mut self, channel_id: u8) -> bool {
+        let density =
+            self.density * self.fade * self.channel_densities.get(&channel_id).unwrap_or(&1.0);
+        // Do not use the rng when all the notes are played, to keep the random choices of the
+        // tracks identical for a given seed.
+        density >= 1.0 || self.rng.gen_bool(density as f64)
+    }
+
+    /// Set the maximum length (in steps) of the notes on the MIDI channel with `channel_id`, or
+    /// remove the limit with `None`. The notes played with [`MidiController::play_note`] are
+    /// shortened, and the notes started with [`MidiController::start_note`] are stopped once they
+    /// reach the limit even if [`MidiController::stop_note`] is never called. This protects
+    /// against endless notes. The limit is at least 1 step.
+    pub fn set_max_gate(&mut self, channel_id: u8, max_gate: Option<u32>) {
+        match max_gate {
+            Some(max_gate) => self.max_gates.insert(channel_id, max_gate.max(1)),
+            None => self.max_gates.remove(&channel_id),
+        };
+    }
+
+    /// Returns the maximum length of the notes on the MIDI channel with `channel_id`, see
+    /// [`MidiController::set_max_gate`].
+    pub fn get_max_gate(&self, channel_id: u8) -> Option<u32> {
+        self.max_gates.get(&channel_id).copied()
+    }
+
+    // Length of a note on channel_id, limited by the max gate.
+    fn gate(&self, channel_id: u8, len: u32) -> u32 {
+        self.get_max_gate(channel_id)
+            .map_or(len, |max| len.min(max))
+    }
+
+    /// Restrict the notes on the MIDI channel with `channel_id` to the range from `low` to `high`
+    /// (velocities are ignored), or remove the range with `None`. The notes outside the range are
+    /// handled according to `policy`. This keeps generated notes in the usable register of a
+    /// synthesizer patch.
+    pub fn set_note_range(
+        &mut self,
+        channel_id: u8,
+        range: Option<(MidiNote, MidiNote)>,
+        policy: RangePolicy,
+    ) {
+        match range {
+            Some((low, high)) => {
+                let (low, high) = (low.midi_value(), high.midi_value());
+                self.note_ranges
+                    .insert(channel_id, (low.min(high), low.max(high), policy))
+            }
+            None => self.note_ranges.remove(&channel_id),
+        };
+    }
+
+    /// Replace the velocity of every note on the MIDI channel with `channel_id` by `velocity`, or
+    /// play the notes with their own velocity with `None`. This is useful for devices that expect
+    /// a fixed velocity.
+    pub fn set_fixed_velocity(&mut self, channel_id: u8, velocity: Option<u8>) {
+        match velocity {
+            Some(velocity) => self.fixed_velocities.insert(channel_id, velocity.min(127)),
+            None => self.fixed_velocities.remove(&channel_id),
+        };
+    }
+
+    /// Clip the velocity of the notes on the MIDI channel with `channel_id` between `floor` and
+    /// `ceiling`, or remove the limits with `None`. The limits are applied after the fixed
+    /// velocity, see [`MidiController::set_fixed_velocity`].
+    pub fn set_velocity_range(&mut self, channel_id: u8, range: Option<(u8, u8)>) {
+        match range {
+            Some((floor, ceiling)) => {
+                let (floor, ceiling) = (floor.min(127), ceiling.min(127));
+                self.velocity_ranges
+                    .insert(channel_id, (floor.min(ceiling), floor.max(ceiling)))
+            }
+            None => self.velocity_ranges.remove(&channel_id),
+        };
+    }
+
+    // Note sent instead of midi_note on channel_id, None if it must not be played.
+    fn output_note(&self, midi_note: MidiNote, channel_id: u8) -> Option<MidiNote> {
+        let mut midi_note = self.fit_range(midi_note, channel_id)?;
+        if let Some(&velocity) = self.fixed_velocities.get(&channel_id) {
+            midi_note.vel = velocity;
+        }
+        if let Some(&(floor, ceiling)) = self.velocity_ranges.get(&channel_id) {
+            midi_note.vel = midi_note.vel.clamp(floor, ceiling);
+        }
+        Some(midi_note)
+    }
+
+    // Note played instead of midi_note according to the range of channel_id, None if it must
+    // not be played.
+    fn fit_range(&self, midi_note: MidiNote, channel_id: u8) -> Option<MidiNote> {
+        let Some(&(low, high, policy)) = self.note_ranges.get(&channel_id) else {
+            return Some(midi_note);
+        };
+        let value = midi_note.midi_value();
+        if (low..=high).contains(&value) {
+            return Some(midi_note);
+        }
+        let clamped = value.clamp(low, high);
+        let value = match policy {
+            RangePolicy::Clamp => clamped,
+            RangePolicy::Fold => {
+                let mut folded = value as i16;
+                while folded < low as i16 {
+                    folded += 12;
+                }
+                while folded > high as i16 {
+                    folded -= 12;
+                }
+                if folded < low as i16 {
+                    clamped
+                } else {
+                    folded as u8
+                }
+            }
+            RangePolicy::Drop => return None,
+        };
+        Some(MidiNote::from_midi_value(value, midi_note.vel))
+    }
+
+    /// Configure the MPE `zone` with `member_channels` member channels (at most 15) by sending an
+    /// MPE Configuration Message on its master channel, or disable the zone with 0. As in the MPE
+    /// specification, the lower and upper zones share 14 member channels when both are
+    /// configured: the other zone is shrunk (or disabled) if it overlaps the new zone, and a MPE
+    /// Configuration Message is sent for it as well.
+    pub fn set_mpe_zone(&mut self, zone: MpeZone, member_channels: u8) {
+        let member_channels = member_channels.min(MAX_MIDI_CHANNEL - 1);
+        let other_zone = match zone {
+            MpeZone::Lower => MpeZone::Upper,
+            MpeZone::Upper => MpeZone::Lower,
+        };
+        if let Some(&(other, _)) = self.mpe_zones.get(&other_zone) {
+            // Each zone also needs its master channel.
+            let available = (MAX_MIDI_CHANNEL - 2).saturating_sub(member_channels);
+            if member_channels > 0 && other > available {
+                self.configure_mpe_zone(other_zone, available);
+            }
+        }
+        self.configure_mpe_zone(zone, member_channels);
+    }
+
+    fn configure_mpe_zone(&mut self, zone: MpeZone, member_channels: u8) {
+        self.send_rpn(
+            zone.master_channel(),
+            RPN_MPE_CONFIGURATION,
+            (member_channels as u16) << 7,
+        );
+        if member_channels == 0 {
+            self.mpe_zones.remove(&zone);
+        } else {
+            self.mpe_zones.insert(zone, (member_channels, 0));
+        }
+    }
+
+    /// Play a note on a member channel of the MPE `zone`, like [`MidiController::play_note`].
+    /// Member channels without any sounding note are used in turn, so that each note gets its own
+    /// channel if possible. Returns the member channel used, on which per-note expression can then
+    /// be sent (e.g., with [`MidiController::send_pitch_bend`] or
+    /// [`MidiController::send_channel_pressure`]), or `None` if the zone is not configured.
+    pub fn play_mpe_note(&mut self, zone: MpeZone, midi_note: MidiNote, len: u32) -> Option<u8> {
+        let &(member_channels, next) = self.mpe_zones.get(&zone)?;
+        let busy = |index: u8| {
+            let channel_id = zone.member_channel(index);
+            self.sounding_notes
+                .range((channel_id, 0)..=(channel_id, u8::MAX))
+                .next()
+                .is_some()
+                || self
+                    .notes_to_play
+                    .iter()
+                    .any(|n| n.channel_id == channel_id)
+        };
+        let index = (0..member_channels)
+            .map(|i| (next + i) % member_channels)
+            .find(|&i| !busy(i))
+            .unwrap_or(next % member_channels);
+        self.mpe_zones
+            .insert(zone, (member_channels, (index + 1) % member_channels));
+
+        let channel_id = zone.member_channel(index);
+        self.play_note(midi_note, len, channel_id);
+        Some(channel_id)
+    }
+
+    /// Report the notes that have been sounding for `threshold` steps or more with a warning, or
+    /// disable the report with `None`. If `release` is `true`, the reported notes are also stopped.
+    /// This allows to find the notes that are never stopped because of a bug in the conductor.
+    pub fn set_stuck_note_threshold(&mut self, threshold: Option<u32>, release: bool) {
+        self.stuck_note_threshold = threshold;
+        self.release_stuck_notes = release;
+    }
+
+    /// Returns the notes that are currently sounding, i.e. the notes for which a note on message
+    /// has been sent and no note off message yet, as `(channel_id, note, since_step)` ordered by
+    /// MIDI channel and note. `since_step` is the step at which the note on message was sent.
+    pub fn sounding_notes(&self) -> impl Iterator<Item = (u8, MidiNote, u32)> + '_ {
+        self.sounding_notes
+            .iter()
+            .map(|(&(channel_id, _), n)| (channel_id, n.midi_note, n.since))
+    }
+
+    // Report the stuck notes, and release them if requested.
+    fn audit_sounding_notes(&mut self) {
+        let Some(threshold) = self.stuck_note_threshold else {
+            return;
+        };
+
+        let mut stuck = vec![];
+        for (&(channel_id, _), n) in &mut self.sounding_notes {
+            if n.reported || self.step < n.since.saturating_add(threshold) {
+                continue;
+            }
+            warn!(
+                "Note {:?} on channel {} has been sounding for {} steps",
+                n.midi_note,
+                channel_id,
+                self.step - n.since
+            );
+            n.reported = true;
+            stuck.push(NotePlay {
+                midi_note: n.midi_note,
+                channel_id,
+            });
+        }
+
+        if self.release_stuck_notes {
+            for n in &stuck {
+                self.start_note_set.remove(n);
+            }
+            self.send_notes_off(&stuck);
+        }
+    }
+
+    /// Enable or disable the drum mode on the MIDI channel with `channel_id`. The notes played by
+    /// a [`crate::DeteTrack`] on a channel in drum mode are never transposed, since each note
+    /// selects a different drum sound.
+    pub fn set_drum_channel(&mut self, channel_id: u8, drum: bool) {
+        if drum {
+            self.drum_channels.insert(channel_id);
+        } else {
+            self.drum_channels.remove(&channel_id);
+        }
+    }
+
+    /// Returns `true` if the MIDI channel with `channel_id` is in drum mode, see
+    /// [`MidiController::set_drum_channel`].
+    pub fn is_drum_channel(&self, channel_id: u8) -> bool {
+        self.drum_channels.contains(&channel_id)
     }
 
     /// Send MIDI Control Change (CC) message. You can use [`crate::param_value`] to convert a
     /// float into a integer.
     pub fn send_cc(&mut self, channel_id: u8, parameter: u8, value: u8) {
+        // A value sent by the user overrides any interpolation in progress.
+        self.cc_morphs
+            .retain(|m| m.channel_id != channel_id || m.parameter != parameter);
+        self.send_cc_value(channel_id, parameter, value);
+    }
+
+    fn send_cc_value(&mut self, channel_id: u8, parameter: u8, value: u8) {
+        let source = (channel_id, parameter);
+        let unmapped = [source];
+        let targets = self
+            .cc_mappings
+            .get(&source)
+            .map_or(&unmapped[..], |t| &t[..]);
+        for &(channel_id, parameter) in targets {
+            if let Err(e) = self.conn.send_cc(channel_id, parameter, value) {
+                error!("MIDI: {e}");
+            }
+        }
+        self.cc_values.insert(source, value);
+    }
+
+    /// Send a 14-bit CC `value` (from 0 to 16383) as the CC `msb_parameter` (from 0 to 31)
+    /// followed by the CC `msb_parameter + 32`, e.g. for smooth filter cutoff sweeps. The most
+    /// significant bits are sent first as required by the MIDI specification. The value is not
+    /// recorded for [`MidiController::get_cc`] and the CC snapshots.
+    pub fn send_cc14(&mut self, channel_id: u8, msb_parameter: u8, value: u16) {
+        if msb_parameter >= 32 {
+            error!("CC {msb_parameter} has no 14-bit pair, message not sent");
+            return;
+        }
+        let value = value.min(0x3fff);
+        self.send_raw_cc(channel_id, msb_parameter, (value >> 7) as u8);
+        self.send_raw_cc(channel_id, msb_parameter + 32, (value & 0x7f) as u8);
+    }
+
+    /// Send the CC `parameter` of the MIDI channel with `channel_id` as the CC `target_parameter`
+    /// on the MIDI channel with `target_channel_id` instead. A CC can be mapped to several
+    /// targets (e.g., to control two synthesizers with a single CC), add a mapping to itself to
+    /// keep sending the original CC. This allows to work around the limited modulation routing of
+    /// some hardware. [`MidiController::get_cc`] still returns the values of the original CC.
+    pub fn add_cc_mapping(
+        &mut self,
+        channel_id: u8,
+        parameter: u8,
+        target_channel_id: u8,
+        target_parameter: u8,
+    ) {
+        let targets = self.cc_mappings.entry((channel_id, parameter)).or_default();
+        if !targets.contains(&(target_channel_id, target_parameter)) {
+            targets.push((target_channel_id, target_parameter));
+        }
+    }
+
+    /// Remove all the mappings of the CC `parameter` of the MIDI channel with `channel_id`, see
+    /// [`MidiController::add_cc_mapping`].
+    pub fn clear_cc_mappings(&mut self, channel_id: u8, parameter: u8) {
+        self.cc_mappings.remove(&(channel_id, parameter));
+    }
+
+    /// Retrieve the last value sent for the CC `parameter` on the MIDI channel with `channel_id`.
+    pub fn get_cc(&self, channel_id: u8, parameter: u8) -> Option<u8> {
+        self.cc_values.get(&(channel_id, parameter)).copied()
+    }
+
+    /// Save the last values sent for every CC parameter on every MIDI channel in a snapshot
+    /// called `name`. If a snapshot with the same name exists, it is replaced.
+    pub fn save_cc_snapshot(&mut self, name: &str) {
+        self.cc_snapshots
+            .insert(name.to_string(), self.cc_values.clone());
+    }
+
+    /// Restore the CC values saved in the snapshot called `name` with
+    /// [`MidiController::save_cc_snapshot`]. If `len` is 0, the values are sent immediately.
+    /// Otherwise, the values are interpolated linearly from their current values over `len` steps.
+    pub fn restore_cc_snapshot(&mut self, name: &str, len: u32) {
+        let Some(snapshot) = self.cc_snapshots.get(name).cloned() else {
+            warn!("CC snapshot {name} not found");
+            return;
+        };
+        self.morph_ccs(&BTreeMap::new(), snapshot, len);
+    }
+
+    /// Crossfade from the CC snapshot called `from` to the CC snapshot called `to` (see
+    /// [`MidiController::save_cc_snapshot`]) over `len` steps, e.g. when switching between two
+    /// scenes. Unlike [`MidiController::restore_cc_snapshot`], the values are interpolated from
+    /// the values of `from` rather than from their current values, which gives the same
+    /// transition whatever the CCs sent before. The CCs missing from `from` start at their
+    /// current values.
+    pub fn crossfade_cc_snapshots(&mut self, from: &str, to: &str, len: u32) {
+        let (Some(from_snapshot), Some(to_snapshot)) =
+            (self.cc_snapshots.get(from), self.cc_snapshots.get(to))
+        else {
+            warn!("CC snapshot {from} or {to} not found");
+            return;
+        };
+        let (from_snapshot, to_snapshot) = (from_snapshot.clone(), to_snapshot.clone());
+        self.morph_ccs(&from_snapshot, to_snapshot, len);
+    }
+
+    // Interpolate the CCs to the values of `to` over `len` steps, starting from the values of
+    // `from` or from the current values.
+    fn morph_ccs(&mut self, from: &BTreeMap<(u8, u8), u8>, to: BTreeMap<(u8, u8), u8>, len: u32) {
+        for ((channel_id, parameter), to) in to {
+            let start = from
+                .get(&(channel_id, parameter))
+                .copied()
+                .or(self.get_cc(channel_id, parameter));
+            match start {
+                Some(from) if len > 0 => {
+                    self.cc_morphs
+                        .retain(|m| m.channel_id != channel_id || m.parameter != parameter);
+                    self.cc_morphs.push(CcMorph {
+                        channel_id,
+                        parameter,
+                        from,
+                        to,
+                        start_step: self.step,
+                        len,
+                    });
+                }
+                _ => self.send_cc(channel_id, parameter, to),
+            }
+        }
+    }
+
+    // Send the interpolated values of the CC morphs at the current step.
+    fn update_cc_morphs(&mut self) {
+        let mut values = vec![];
+        self.cc_morphs.retain(|m| {
+            let elapsed = (self.step.saturating_sub(m.start_step) + 1).min(m.len);
+            let value =
+                m.from as f32 + (m.to as f32 - m.from as f32) * elapsed as f32 / m.len as f32;
+            values.push((m.channel_id, m.parameter, value.round() as u8));
+            elapsed < m.len
+        });
+
+        for (channel_id, parameter, value) in values {
+            if self.get_cc(channel_id, parameter) != Some(value) {
+                self.send_cc_value(channel_id, parameter, value);
+            }
+        }
+    }
+
+    /// Send MIDI Bank Select messages (CC 0 followed by CC 32) to select the bank `(msb, lsb)` on
+    /// the MIDI channel with `channel_id`. The bank will only be loaded by the device after the
+    /// next [`MidiController::send_program_change`].
+    pub fn send_bank_select(&mut self, channel_id: u8, msb: u8, lsb: u8) {
+        self.send_raw_cc(channel_id, BANK_SELECT_MSB, msb);
+        self.send_raw_cc(channel_id, BANK_SELECT_LSB, lsb);
+        self.programs.entry(channel_id).or_default().bank = Some((msb, lsb));
+    }
+
+    /// Set the Non-Registered Parameter Number (NRPN) `parameter` to `value` (both from 0 to 16383)
+    /// on the MIDI channel with `channel_id`, by sending CC 99, 98, 6 and 38. The parameter is
+    /// then deselected with the RPN null so that later data entries don't modify it.
+    pub fn send_nrpn(&mut self, channel_id: u8, parameter: u16, value: u16) {
+        self.send_parameter_number(channel_id, (NRPN_MSB, NRPN_LSB), parameter, value);
+        self.select_parameter_number(channel_id, (RPN_MSB, RPN_LSB), RPN_NULL);
+    }
+
+    /// Set the Registered Parameter Number (RPN) `parameter` to `value` (both from 0 to 16383) on
+    /// the MIDI channel with `channel_id`, by sending CC 101, 100, 6 and 38, e.g. RPN 0 for the
+    /// pitch bend range. The parameter is then deselected with the RPN null.
+    pub fn send_rpn(&mut self, channel_id: u8, parameter: u16, value: u16) {
+        self.send_parameter_number(channel_id, (RPN_MSB, RPN_LSB), parameter, value);
+        self.select_parameter_number(channel_id, (RPN_MSB, RPN_LSB), RPN_NULL);
+    }
+
+    fn send_parameter_number(
+        &mut self,
+        channel_id: u8,
+        select_ccs: (u8, u8),
+        parameter: u16,
+        value: u16,
+    ) {
+        self.select_parameter_number(channel_id, select_ccs, parameter);
+        let value = value.min(0x3fff);
+        self.send_raw_cc(channel_id, DATA_ENTRY_MSB, (value >> 7) as u8);
+        self.send_raw_cc(channel_id, DATA_ENTRY_LSB, (value & 0x7f) as u8);
+    }
+
+    fn select_parameter_number(&mut self, channel_id: u8, select_ccs: (u8, u8), parameter: u16) {
+        let parameter = parameter.min(0x3fff);
+        self.send_raw_cc(channel_id, select_ccs.0, (parameter >> 7) as u8);
+        self.send_raw_cc(channel_id, select_ccs.1, (parameter & 0x7f) as u8);
+    }
+
+    // Send a CC that is part of a multi-message sequence (bank select, RPN/NRPN, 14-bit CC)
+    // straight to the connection. These CCs are neither mapped nor recorded in the CC values, so
+    // that snapshots and crossfades never replay a partial sequence.
+    fn send_raw_cc(&mut self, channel_id: u8, parameter: u8, value: u8) {
         if let Err(e) = self.conn.send_cc(channel_id, parameter, value) {
             error!("MIDI: {e}");
         }
     }
 
+    /// Send MIDI Program Change message to select `program` on the MIDI channel with
+    /// `channel_id`.
+    pub fn send_program_change(&mut self, channel_id: u8, program: u8) {
+        if let Err(e) = self.conn.send_program_change(channel_id, program) {
+            error!("MIDI: {e}");
+        }
+        self.programs.entry(channel_id).or_default().program = Some(program);
+    }
+
+    /// Send MIDI Pitch Bend message with `value` (from 0 to 16383, 8192 is the center) on the MIDI
+    /// channel with `channel_id`. This allows to play slides and vibratos.
+    pub fn send_pitch_bend(&mut self, channel_id: u8, value: u16) {
+        if let Err(e) = self.conn.send_pitch_bend(channel_id, value.min(0x3fff)) {
+            error!("MIDI: {e}");
+        }
+    }
+
+    /// Send MIDI Channel Pressure (aftertouch) message with `pressure` (from 0 to 127) on the MIDI
+    /// channel with `channel_id`.
+    pub fn send_channel_pressure(&mut self, channel_id: u8, pressure: u8) {
+        if let Err(e) = self
+            .conn
+            .send_channel_pressure(channel_id, pressure.min(127))
+        {
+            error!("MIDI: {e}");
+        }
+    }
+
+    /// Send MIDI Polyphonic Key Pressure (poly aftertouch) message with `pressure` (from 0 to 127)
+    /// for `midi_note` on the MIDI channel with `channel_id`. The velocity of `midi_note` is
+    /// ignored, and the note range of the channel is applied as for the notes played (see
+    /// [`MidiController::set_note_range`]).
+    pub fn send_poly_pressure(&mut self, midi_note: MidiNote, channel_id: u8, pressure: u8) {
+        let Some(midi_note) = self.fit_range(midi_note, channel_id) else {
+            return;
+        };
+        let note = midi_note.midi_value();
+        if let Err(e) = self
+            .conn
+            .send_poly_pressure(channel_id, note, pressure.min(127))
+        {
+            error!("MIDI: {e}");
+        }
+    }
+
+    /// Send MIDI System Exclusive message with `data`, e.g. a patch dump. `data` must not contain
+    /// the start (0xF0) and end (0xF7) bytes, they are added by the MIDI connection. The message
+    /// is not sent if `data` contains a status byte (0x80 or above).
+    pub fn send_sysex(&mut self, data: &[u8]) {
+        if let Some(byte) = data.iter().find(|&&b| b >= 0x80) {
+            error!("Invalid SysEx data byte {byte:#04x}, message not sent");
+            return;
+        }
+        if let Err(e) = self.conn.send_sysex(data) {
+            error!("MIDI: {e}");
+        }
+    }
+
+    /// Retrieve the last bank `(msb, lsb)` sent on the MIDI channel with `channel_id` with
+    /// [`MidiController::send_bank_select`].
+    pub fn get_bank(&self, channel_id: u8) -> Option<(u8, u8)> {
+        self.programs.get(&channel_id).and_then(|p| p.bank)
+    }
+
+    /// Retrieve the last program sent on the MIDI channel with `channel_id` with
+    /// [`MidiController::send_program_change`].
+    pub fn get_program(&self, channel_id: u8) -> Option<u8> {
+        self.programs.get(&channel_id).and_then(|p| p.program)
+    }
+
+    /// Send again the last bank and program sent on every MIDI channel. This is useful to restore
+    /// the patches of a device that has been power-cycled or reconnected.
+    pub fn resend_programs(&mut self) {
+        let programs: Vec<_> = self.programs.iter().map(|(c, p)| (*c, *p)).collect();
+        for (channel_id, state) in programs {
+            if let Some((msb, lsb)) = state.bank {
+                self.send_bank_select(channel_id, msb, lsb);
+            }
+            if let Some(program) = state.program {
+                self.send_program_change(channel_id, program);
+            }
+        }
+    }
+
     pub(crate) fn send_clock(&mut self) {
         if let Err(e) = self.conn.send_clock() {
             error!("MIDI: {e}");
@@ -153,11 +1039,24 @@ impl<T: MidiConnection> MidiController<T> {
 
     pub(crate) fn start(&mut self) {
         self.step = 0;
+        self.scheduled_notes.clear();
         if let Err(e) = self.conn.send_start() {
             error!("MIDI: {e}");
         }
     }
 
+    // Move to another step, the notes have to be stopped beforehand.
+    pub(crate) fn jump(&mut self, step: u32) {
+        self.step = step;
+        self.scheduled_notes.clear();
+    }
+
+    pub(crate) fn send_song_position(&mut self, position: u16) {
+        if let Err(e) = self.conn.send_song_position(position) {
+            error!("MIDI: {e}");
+        }
+    }
+
     pub(crate) fn send_continue(&mut self) {
         if let Err(e) = self.conn.send_continue() {
             error!("MIDI: {e}");
@@ -165,57 +1064,129 @@ impl<T: MidiConnection> MidiController<T> {
     }
 
     pub(crate) fn update(&mut self, next_step: u32) {
-        // First send the off signal to every note that end this step.
-        let notes = self.play_note_set.remove(&self.step);
-        if let Some(notes_off) = notes {
-            for n in notes_off {
-                if let Err(e) = self
-                    .conn
-                    .send_note_off(n.channel_id, n.midi_note.midi_value())
-                {
-                    error!("MIDI: {e}");
+        if let Some(notes) = self.scheduled_notes.remove(&self.step) {
+            for (note_play, len) in notes {
+                self.notes_to_play.push(note_play);
+                self.stop_note_at_step(note_play, self.step + len);
+            }
+        }
+
+        // Also stop the notes that should have been stopped before this step, e.g. after a jump.
+        let mut notes_off = vec![];
+        while let Some(Reverse(off)) = self.play_note_set.peek() {
+            if off.step > self.step {
+                break;
+            }
+            notes_off.push(off.note_play);
+            self.play_note_set.pop();
+        }
+
+        // Stop the started notes that reached the max gate of their channel.
+        let (step, max_gates) = (self.step, &self.max_gates);
+        self.start_note_set
+            .retain(|n, start| match max_gates.get(&n.channel_id) {
+                Some(max_gate) if step >= start.saturating_add(*max_gate) => {
+                    warn!("Max gate reached on channel {}, note stopped", n.channel_id);
+                    notes_off.push(*n);
+                    false
+                }
+                _ => true,
+            });
+        let mut notes_on = std::mem::take(&mut self.notes_to_play);
+
+        // Notes that end and start again at this step.
+        let mut retriggered = vec![];
+        notes_off.retain(|off| {
+            let on = notes_on.iter().position(|on| {
+                on.channel_id == off.channel_id
+                    && on.midi_note.midi_value() == off.midi_note.midi_value()
+            });
+            match (on, self.retrigger_policy) {
+                (Some(_), RetriggerPolicy::Retrigger) => {
+                    retriggered.push(*off);
+                    false
+                }
+                (Some(i), RetriggerPolicy::Tie) => {
+                    notes_on.remove(i);
+                    false
                 }
+                (None, _) => true,
             }
-        };
+        });
 
-        // Then play all the notes that were triggered this step...
-        for n in &self.notes_to_play {
-            if let Err(e) =
-                self.conn
-                    .send_note_on(n.channel_id, n.midi_note.midi_value(), n.midi_note.vel)
-            {
-                error!("MIDI: {e}");
+        self.update_cc_morphs();
+
+        // A retriggered note always needs to be stopped before being played again.
+        self.send_notes_off(&retriggered);
+        match self.note_order {
+            NoteOrder::OffFirst => {
+                self.send_notes_off(&notes_off);
+                self.send_notes_on(&notes_on);
+            }
+            NoteOrder::OnFirst => {
+                self.send_notes_on(&notes_on);
+                self.send_notes_off(&notes_off);
             }
         }
-        // ...and clear them.
-        self.notes_to_play.clear();
+        self.audit_sounding_notes();
 
         // Finally update the step.
         self.step = next_step;
     }
 
-    pub(crate) fn stop_all_notes(&mut self) {
-        self.start_note_set.iter().for_each(|n| {
+    // Only the notes that are sounding are stopped, so that a note is never stopped twice.
+    fn send_notes_off(&mut self, notes: &[NotePlay]) {
+        for n in notes {
+            let key = (n.channel_id, n.midi_note.midi_value());
+            if self.sounding_notes.remove(&key).is_none() {
+                continue;
+            }
             if let Err(e) = self
                 .conn
                 .send_note_off(n.channel_id, n.midi_note.midi_value())
             {
                 error!("MIDI: {e}");
             }
-        });
-        self.start_note_set.clear();
+        }
+    }
 
-        self.play_note_set.values().for_each(|notes| {
-            for n in notes {
-                if let Err(e) = self
-                    .conn
-                    .send_note_off(n.channel_id, n.midi_note.midi_value())
-                {
-                    error!("MIDI: {e}");
-                }
+    fn send_notes_on(&mut self, notes: &[NotePlay]) {
+        for n in notes {
+            self.sounding_notes.insert(
+                (n.channel_id, n.midi_note.midi_value()),
+                SoundingNote {
+                    midi_note: n.midi_note,
+                    since: self.step,
+                    reported: false,
+                },
+            );
+            if let Err(e) =
+                self.conn
+                    .send_note_on(n.channel_id, n.midi_note.midi_value(), n.midi_note.vel)
+            {
+                error!("MIDI: {e}");
             }
-        });
+        }
+    }
+
+    pub(crate) fn stop_all_notes(&mut self) {
+        self.start_note_set.clear();
         self.play_note_set.clear();
+        // The notes requested at this step must not be played after a pause, since their note off
+        // messages have just been discarded.
+        self.notes_to_play.clear();
+        self.scheduled_notes.clear();
+
+        // Stop every sounding note, ordered by MIDI channel and note.
+        let notes: Vec<NotePlay> = self
+            .sounding_notes
+            .iter()
+            .map(|(&(channel_id, _), n)| NotePlay {
+                midi_note: n.midi_note,
+                channel_id,
+            })
+            .collect();
+        self.send_notes_off(&notes);
     }
 
     pub(crate) fn stop(&mut self) {
@@ -0,0 +1,55 @@
+use crate::{Conductor, Context, MidiConnection, MidiController, RunSummary, TextConnection};
+
+/// Run the sequencer like [`crate::run_with_connection`], but without waiting between the clock
+/// ticks: the ticks are processed as fast as possible, and the run stops after `max_ticks` ticks
+/// if the conductor has not quit before. This allows to check the output of a conductor without
+/// playing it, or to measure how long it takes to compute.
+pub fn run_offline<T: MidiConnection>(
+    mut conductor: impl Conductor,
+    conn: T,
+    max_ticks: u32,
+) -> RunSummary {
+    let mut ctx = Context::new(MidiController::new(conn));
+    ctx.clock.set_offline();
+    ctx.max_ticks = Some(max_ticks);
+    conductor.init(&mut ctx);
+    ctx.run(conductor)
+}
+
+/// Run the conductors `a` and `b` offline (see [`run_offline`]) for at most `max_ticks` clock
+/// ticks and compare the MIDI messages they send, clock ticks included. Both conductors start
+/// with the same random seed (see [`MidiController::set_seed`]). Returns `None` if the messages
+/// are identical, otherwise the index of the first different message and the messages of `a` and
+/// `b` in a human-readable form (empty if the conductor sent fewer messages). This is useful to
+/// verify that a refactored conductor plays exactly the same music.
+pub fn compare_conductors(
+    a: impl Conductor,
+    b: impl Conductor,
+    max_ticks: u32,
+) -> Option<(usize, String, String)> {
+    let messages_a = record(a, max_ticks);
+    let messages_b = record(b, max_ticks);
+    let len = messages_a.len().max(messages_b.len());
+    (0..len).find_map(|i| {
+        let message_a = messages_a.get(i).cloned().unwrap_or_default();
+        let message_b = messages_b.get(i).cloned().unwrap_or_default();
+        (message_a != message_b).then_some((i, message_a, message_b))
+    })
+}
+
+// Run the conductor offline and return the messages it sends, one per line.
+fn record(mut conductor: impl Conductor, max_ticks: u32) -> Vec<String> {
+    let mut out = vec![];
+    {
+        let mut ctx = Context::new(MidiController::new(TextConnection::new(&mut out, true)));
+        ctx.clock.set_offline();
+        ctx.max_ticks = Some(max_ticks);
+        ctx.midi.set_seed(0);
+        conductor.init(&mut ctx);
+        ctx.run(conductor);
+    }
+    String::from_utf8_lossy(&out)
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
@@ -0,0 +1,107 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{MidiConnection, MidiController};
+
+/// Registry of named parameters (from 0.0 to 1.0) of a conductor, accessed with
+/// [`crate::Context::param`]. Parameters give a standard way to expose tweakable values (e.g., a
+/// filter cutoff) to the outside world. Each parameter can be bound to a CC, sent every time the
+/// parameter changes. Only the values are serialized, which allows to save and restore them with
+/// any serde format.
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Params {
+    values: BTreeMap<String, f32>,
+    // (channel, CC parameter) bound to each parameter.
+    #[serde(skip)]
+    ccs: BTreeMap<String, (u8, u8)>,
+    // Parameters changed since the last call to Params::take_changed.
+    #[serde(skip)]
+    changed: BTreeSet<String>,
+}
+
+impl Params {
+    /// Retrieve the value of the parameter called `name`.
+    pub fn get(&self, name: &str) -> Option<f32> {
+        self.values.get(name).copied()
+    }
+
+    /// Iterate over the parameters as `(name, value)`, ordered by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, f32)> {
+        self.values
+            .iter()
+            .map(|(name, &value)| (name.as_str(), value))
+    }
+
+    /// Returns the names of the parameters that changed since the last call, ordered by name.
+    pub fn take_changed(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.changed).into_iter().collect()
+    }
+
+    // Set the values of `params`, the CC bindings are kept.
+    pub(crate) fn load(&mut self, params: Params, midi: &mut MidiController<impl MidiConnection>) {
+        for (name, value) in params.values {
+            Param {
+                name: &name,
+                params: self,
+                midi,
+            }
+            .set(value);
+        }
+    }
+}
+
+/// Parameter of [`Params`] returned by [`crate::Context::param`].
+pub struct Param<'a, T: MidiConnection> {
+    name: &'a str,
+    params: &'a mut Params,
+    midi: &'a mut MidiController<T>,
+}
+
+impl<'a, T: MidiConnection> Param<'a, T> {
+    pub(crate) fn new(
+        name: &'a str,
+        params: &'a mut Params,
+        midi: &'a mut MidiController<T>,
+    ) -> Self {
+        if !params.values.contains_key(name) {
+            params.values.insert(name.to_string(), 0.0);
+        }
+        Self { name, params, midi }
+    }
+
+    /// Retrieve the value of the parameter.
+    pub fn get(&self) -> f32 {
+        self.params.values[self.name]
+    }
+
+    /// Set the value of the parameter, clamped between 0.0 and 1.0. Non-finite values (e.g., NaN)
+    /// are ignored. If the value changes, the parameter is reported by [`Params::take_changed`] and
+    /// the bound CC is sent.
+    pub fn set(&mut self, value: f32) {
+        if !value.is_finite() {
+            warn!("Non-finite value ignored for parameter {}", self.name);
+            return;
+        }
+        let value = value.clamp(0.0, 1.0);
+        if self.get() == value {
+            return;
+        }
+        self.params.values.insert(self.name.to_string(), value);
+        self.params.changed.insert(self.name.to_string());
+        if let Some(&(channel_id, parameter)) = self.params.ccs.get(self.name) {
+            self.midi
+                .send_cc(channel_id, parameter, crate::param_value(value));
+        }
+    }
+
+    /// Bind the parameter to the CC `parameter` on the MIDI channel with `channel_id`: the CC is
+    /// sent every time the value of the parameter changes.
+    pub fn bind_cc(&mut self, channel_id: u8, parameter: u8) {
+        self.params
+            .ccs
+            .insert(self.name.to_string(), (channel_id, parameter));
+    }
+}
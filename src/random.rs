@@ -0,0 +1,66 @@
+use crate::{DeteTrack, MidiNote};
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+// Semitone intervals used by DeteTrack::mutate.
+const MUTATION_INTERVALS: [i8; 6] = [-12, -2, -1, 1, 2, 12];
+
+impl DeteTrack {
+    /// Return a copy of the track where the notes starting at the same step are moved together to
+    /// another step that has notes, chosen randomly. The rhythm (the steps that have notes) is
+    /// kept, but the content of the steps is shuffled. Use [`crate::MidiController::rng`] or a
+    /// seeded [`rand::rngs::StdRng`] as `rng` to get reproducible results.
+    pub fn shuffle_steps(&self, rng: &mut impl Rng) -> Self {
+        let mut steps: Vec<u32> = self.notes.iter().map(|n| n.1).collect();
+        steps.sort();
+        steps.dedup();
+
+        let mut shuffled = steps.clone();
+        shuffled.shuffle(rng);
+
+        let mut track = self.clone();
+        for n in &mut track.notes {
+            let i = steps.binary_search(&n.1).unwrap_or_default();
+            n.1 = shuffled[i];
+        }
+        track.sort_by_step();
+        track
+    }
+
+    /// Return a copy of the track where each note is kept with the probability `density` (from
+    /// 0.0 to 1.0). A density of 1.0 keeps all the notes, and a density of 0.0 removes them all.
+    pub fn density(&self, density: f32, rng: &mut impl Rng) -> Self {
+        let density = density.clamp(0.0, 1.0) as f64;
+        let mut track = self.clone();
+        track.notes.retain(|_| rng.gen_bool(density));
+        track
+    }
+
+    /// Return a copy of the track where each note is mutated with the probability `amount` (from
+    /// 0.0 to 1.0). A mutated note is transposed by a small interval (one or two semitones, or an
+    /// octave) and its velocity is changed by up to 25%.
+    pub fn mutate(&self, amount: f32, rng: &mut impl Rng) -> Self {
+        let amount = amount.clamp(0.0, 1.0) as f64;
+        let mut track = self.clone();
+        for n in &mut track.notes {
+            if !rng.gen_bool(amount) {
+                continue;
+            }
+
+            let interval = *MUTATION_INTERVALS.choose(rng).unwrap();
+            let midi_value = n.0.midi_value() as i16 + interval as i16;
+            let note = if (0..=127).contains(&midi_value) {
+                MidiNote::from_midi_value(midi_value as u8, n.0.vel)
+            } else {
+                n.0
+            };
+
+            let vel = (note.vel as f32 * rng.gen_range(0.75..=1.25)).round();
+            n.0 = MidiNote {
+                vel: vel.clamp(1.0, 127.0) as u8,
+                ..note
+            };
+        }
+        track
+    }
+}
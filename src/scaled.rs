@@ -0,0 +1,112 @@
+use log::warn;
+
+use crate::{MidiConnection, MidiController, Note, Track, BAR_LEN};
+
+/// Wrapper playing a [`Track`] at a multiplied or divided rate, e.g. half-time drums against a
+/// full-rate bassline. The wrapped track is called with a scaled step counter, every step of the
+/// scaled counter is played exactly once. The lengths of the notes played by the wrapped track
+/// are not scaled.
+pub struct ScaledTrack<T: Track> {
+    track: T,
+    multiplier: u32,
+    divider: u32,
+    // Rate applied on the next bar boundary.
+    pending: Option<(u32, u32)>,
+    // Step and scaled step at which the current rate was applied.
+    origin: (u32, u32),
+    // Next scaled step to play.
+    next: u32,
+}
+
+impl<T: Track> ScaledTrack<T> {
+    /// Play `track` at `multiplier / divider` times the rate of the sequencer.
+    pub fn new(track: T, multiplier: u32, divider: u32) -> Self {
+        let mut scaled = Self {
+            track,
+            multiplier: 1,
+            divider: 1,
+            pending: None,
+            origin: (0, 0),
+            next: 0,
+        };
+        if let Some(rate) = Self::check_rate(multiplier, divider) {
+            (scaled.multiplier, scaled.divider) = rate;
+        }
+        scaled
+    }
+
+    /// Change the rate to `multiplier / divider`. The new rate is applied on the next bar
+    /// boundary so that the wrapped track stays aligned to the bars.
+    pub fn set_rate(&mut self, multiplier: u32, divider: u32) {
+        if let Some(rate) = Self::check_rate(multiplier, divider) {
+            self.pending = Some(rate);
+        }
+    }
+
+    /// Returns the current rate as `(multiplier, divider)`.
+    pub fn get_rate(&self) -> (u32, u32) {
+        (self.multiplier, self.divider)
+    }
+
+    /// Returns the wrapped track.
+    pub fn inner(&mut self) -> &mut T {
+        &mut self.track
+    }
+
+    fn check_rate(multiplier: u32, divider: u32) -> Option<(u32, u32)> {
+        if multiplier == 0 || divider == 0 {
+            warn!("Invalid track rate {multiplier}/{divider}, ignoring it");
+            None
+        } else {
+            Some((multiplier, divider))
+        }
+    }
+
+    fn scaled_step(&self, step: u32) -> u32 {
+        self.origin.1 + (step - self.origin.0) * self.multiplier / self.divider
+    }
+}
+
+impl<T: Track> Track for ScaledTrack<T> {
+    fn play_step(&mut self, step: u32, midi_controller: &mut MidiController<impl MidiConnection>) {
+        if step < self.origin.0 {
+            // The sequencer restarted or jumped backwards.
+            self.origin = (0, 0);
+        }
+        if step.is_multiple_of(BAR_LEN) {
+            if let Some((multiplier, divider)) = self.pending.take() {
+                self.origin = (step, self.scaled_step(step));
+                self.multiplier = multiplier;
+                self.divider = divider;
+            }
+        }
+
+        let target = self.scaled_step(step);
+        // Only catch up on the scaled steps of a single step, not after a jump.
+        if target + 1 < self.next
+            || target.saturating_sub(self.next) >= self.multiplier.div_ceil(self.divider)
+        {
+            self.next = target;
+        }
+        while self.next <= target {
+            self.track.play_step(self.next, midi_controller);
+            self.next += 1;
+        }
+    }
+
+    fn transpose(&mut self, note: Option<Note>) {
+        self.track.transpose(note)
+    }
+
+    fn get_root(&self) -> Note {
+        self.track.get_root()
+    }
+
+    fn set_start_step(&mut self, start_step: u32) {
+        self.track.set_start_step(start_step)
+    }
+
+    fn get_name(&self) -> String {
+        self.track.get_name()
+    }
+}
@@ -0,0 +1,118 @@
+#[cfg(feature = "midly")]
+use std::path::Path;
+
+#[cfg(feature = "midly")]
+use crate::track::TrackError;
+#[cfg(feature = "midly")]
+use crate::MSeqError;
+use crate::BAR_LEN;
+
+// Number of steps in a quarter note.
+#[cfg(feature = "midly")]
+const STEPS_PER_BEAT: u64 = 24;
+
+#[derive(Clone, Copy, Debug)]
+struct TempoPoint {
+    step: u32,
+    bpm: f32,
+    // Reach the bpm with a linear ramp from the previous point.
+    ramp: bool,
+}
+
+/// Tempo of a song as a list of points, set with [`crate::Context::set_tempo_map`] so that the
+/// tempo follows the arrangement automatically. Each point sets the BPM at the start of a bar,
+/// either immediately ([`TempoMap::add_point`]) or with a linear ramp from the previous point
+/// ([`TempoMap::add_ramp`]).
+#[derive(Clone, Default, Debug)]
+pub struct TempoMap {
+    // Sorted by step, at most one point per step.
+    points: Vec<TempoPoint>,
+}
+
+impl TempoMap {
+    /// Create an empty tempo map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the BPM to `bpm` at the start of `bar` (starting at 0). A point already set at this
+    /// bar is replaced.
+    pub fn add_point(&mut self, bar: u32, bpm: f32) {
+        self.insert(TempoPoint {
+            step: bar * BAR_LEN,
+            bpm,
+            ramp: false,
+        });
+    }
+
+    /// Change the BPM linearly from the previous point to reach `bpm` at the start of `bar`
+    /// (starting at 0). A point already set at this bar is replaced. If there is no previous
+    /// point, this is the same as [`TempoMap::add_point`].
+    pub fn add_ramp(&mut self, bar: u32, bpm: f32) {
+        self.insert(TempoPoint {
+            step: bar * BAR_LEN,
+            bpm,
+            ramp: true,
+        });
+    }
+
+    /// Returns the BPM at `step`, or `None` if there is no point at or before `step`.
+    pub fn bpm_at(&self, step: u32) -> Option<f32> {
+        let i = self.points.partition_point(|p| p.step <= step);
+        let prev = self.points[..i].last()?;
+        match self.points.get(i) {
+            Some(next) if next.ramp => {
+                let progress = (step - prev.step) as f32 / (next.step - prev.step) as f32;
+                Some(prev.bpm + (next.bpm - prev.bpm) * progress)
+            }
+            _ => Some(prev.bpm),
+        }
+    }
+
+    /// Load the tempo map from the tempo changes of a midi file. The tempo changes are kept at
+    /// their exact step, even if they are not at the start of a bar.
+    #[cfg(feature = "midly")]
+    pub fn load_from_midi_file<P: AsRef<Path>>(filename: P) -> Result<Self, MSeqError> {
+        Self::parse_midi_file(&filename).map_err(|source| MSeqError::Track {
+            path: filename.as_ref().to_path_buf(),
+            source,
+        })
+    }
+
+    #[cfg(feature = "midly")]
+    fn parse_midi_file<P: AsRef<Path>>(filename: P) -> Result<Self, TrackError> {
+        let bytes = fs_err::read(filename)?;
+        let smf = midly::Smf::parse(&bytes)?;
+
+        let ticks_per_beat = match smf.header.timing {
+            midly::Timing::Metrical(t) if u16::from(t) > 0 => u16::from(t) as u64,
+            _ => return Err(TrackError::BadTiming),
+        };
+
+        let mut tempo_map = Self::new();
+        for track in &smf.tracks {
+            let mut tick = 0;
+            for event in track {
+                tick += u32::from(event.delta) as u64;
+                if let midly::TrackEventKind::Meta(midly::MetaMessage::Tempo(us_per_beat)) =
+                    event.kind
+                {
+                    let bpm = 60_000_000.0 / u32::from(us_per_beat).max(1) as f32;
+                    tempo_map.insert(TempoPoint {
+                        step: (tick * STEPS_PER_BEAT / ticks_per_beat) as u32,
+                        bpm,
+                        ramp: false,
+                    });
+                }
+            }
+        }
+        Ok(tempo_map)
+    }
+
+    fn insert(&mut self, point: TempoPoint) {
+        match self.points.binary_search_by_key(&point.step, |p| p.step) {
+            Ok(i) => self.points[i] = point,
+            Err(i) => self.points.insert(i, point),
+        }
+    }
+}
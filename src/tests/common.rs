@@ -1,9 +1,10 @@
-use crate::clock::Clock;
 use crate::Conductor;
 use crate::Context;
 use crate::MidiConnection;
 use crate::MidiController;
 use crate::MidiError;
+use crate::RunSummary;
+use crate::TransportState;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
@@ -45,6 +46,12 @@ impl MidiConnection for DebugMidiConnection {
         Ok(())
     }
 
+    fn send_song_position(&mut self, position: u16) -> Result<(), MidiError> {
+        let message = format!("Spp\tpos:{}", position);
+        self.print_elapsed(&message);
+        Ok(())
+    }
+
     fn send_note_on(&mut self, channel_id: u8, note: u8, velocity: u8) -> Result<(), MidiError> {
         let message = format!("On\tchn:{}\tnte:{}\tvel:{}", channel_id, note, velocity);
         self.print_elapsed(&message);
@@ -69,20 +76,62 @@ impl MidiConnection for DebugMidiConnection {
         self.print_elapsed(&message);
         Ok(())
     }
+
+    fn send_program_change(&mut self, channel_id: u8, program: u8) -> Result<(), MidiError> {
+        let message = format!("Pc\tchn:{}\tprg:{}", channel_id, program);
+        self.print_elapsed(&message);
+        Ok(())
+    }
+
+    fn send_pitch_bend(&mut self, channel_id: u8, value: u16) -> Result<(), MidiError> {
+        let message = format!("Pb\tchn:{}\tval:{}", channel_id, value);
+        self.print_elapsed(&message);
+        Ok(())
+    }
+
+    fn send_channel_pressure(&mut self, channel_id: u8, pressure: u8) -> Result<(), MidiError> {
+        let message = format!("Cp\tchn:{}\tprs:{}", channel_id, pressure);
+        self.print_elapsed(&message);
+        Ok(())
+    }
+
+    fn send_poly_pressure(
+        &mut self,
+        channel_id: u8,
+        note: u8,
+        pressure: u8,
+    ) -> Result<(), MidiError> {
+        let message = format!("Pp\tchn:{}\tnte:{}\tprs:{}", channel_id, note, pressure);
+        self.print_elapsed(&message);
+        Ok(())
+    }
+
+    fn send_sysex(&mut self, data: &[u8]) -> Result<(), MidiError> {
+        let message = format!("Sx\tlen:{}", data.len());
+        self.print_elapsed(&message);
+        Ok(())
+    }
+}
+
+pub(super) fn debug_controller() -> (
+    MidiController<DebugMidiConnection>,
+    Rc<RefCell<DebugMidiConnectionInner>>,
+) {
+    let debug_conn = Rc::new(RefCell::new(DebugMidiConnectionInner {
+        notes_on: HashMap::new(),
+        start_timestamp: Instant::now(),
+    }));
+    let mut controller = MidiController::new(DebugMidiConnection(debug_conn.clone()));
+    controller.start();
+    (controller, debug_conn)
 }
 
 pub(super) fn test_conductor<T: MidiConnection>(
     mut conductor: impl Conductor,
     midi: MidiController<T>,
-) {
-    let mut ctx = Context {
-        midi,
-        clock: Clock::new(120),
-        step: 0,
-        running: true,
-        on_pause: false,
-        pause: false,
-    };
+) -> RunSummary {
+    let mut ctx = Context::new(midi);
+    ctx.transport = TransportState::Playing;
     conductor.init(&mut ctx);
-    ctx.run(conductor);
+    ctx.run(conductor)
 }
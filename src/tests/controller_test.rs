@@ -0,0 +1,431 @@
+use super::common::debug_controller;
+use super::common::DebugMidiConnection;
+use crate::MidiController;
+use crate::MidiNote;
+use crate::MpeZone;
+use crate::Note;
+use crate::NoteOrder;
+use crate::RangePolicy;
+use crate::RetriggerPolicy;
+
+#[test]
+fn cc_snapshot_morph() {
+    let (mut controller, _) = debug_controller();
+
+    controller.send_cc(0, 74, 0);
+    controller.send_cc(1, 71, 100);
+    controller.save_cc_snapshot("open");
+    controller.send_cc(0, 74, 120);
+    controller.send_cc(1, 71, 10);
+
+    controller.restore_cc_snapshot("open", 4);
+    let mut values = vec![];
+    for step in 0..6 {
+        controller.update(step + 1);
+        values.push(controller.get_cc(0, 74).unwrap());
+    }
+    assert!(values == vec![90, 60, 30, 0, 0, 0]);
+    assert!(controller.get_cc(1, 71) == Some(100));
+
+    // Immediate restore
+    controller.send_cc(0, 74, 120);
+    controller.restore_cc_snapshot("open", 0);
+    assert!(controller.get_cc(0, 74) == Some(0));
+}
+
+fn play_retriggered_note(controller: &mut MidiController<DebugMidiConnection>) {
+    controller.play_note(MidiNote::new(Note::A, 3, 10), 2, 0);
+    controller.update(1);
+    controller.update(2);
+    controller.play_note(MidiNote::new(Note::A, 3, 20), 2, 0);
+    controller.update(3);
+}
+
+#[test]
+fn retrigger_note_off_before_note_on() {
+    let (mut controller, debug_conn) = debug_controller();
+    controller.set_note_order(NoteOrder::OnFirst);
+    play_retriggered_note(&mut controller);
+    let key = MidiNote::new(Note::A, 3, 0).midi_value();
+    assert!(debug_conn.borrow().notes_on.get(&(0, key)) == Some(&20));
+
+    controller.update(4);
+    assert!(debug_conn.borrow().notes_on.get(&(0, key)) == Some(&20));
+    controller.update(5);
+    assert!(debug_conn.borrow().notes_on.is_empty());
+}
+
+#[test]
+fn retrigger_tie() {
+    let (mut controller, debug_conn) = debug_controller();
+    controller.set_retrigger_policy(RetriggerPolicy::Tie);
+    play_retriggered_note(&mut controller);
+    let key = MidiNote::new(Note::A, 3, 0).midi_value();
+    assert!(debug_conn.borrow().notes_on.get(&(0, key)) == Some(&10));
+
+    controller.update(4);
+    assert!(debug_conn.borrow().notes_on.get(&(0, key)) == Some(&10));
+    controller.update(5);
+    assert!(debug_conn.borrow().notes_on.is_empty());
+}
+
+#[test]
+fn play_note_at_offset() {
+    let (mut controller, debug_conn) = debug_controller();
+    let note = MidiNote::new(Note::E, 4, 64);
+    controller.play_note_at(note, 3, 2, 1);
+
+    let mut notes_on = vec![];
+    for step in 0..8 {
+        controller.update(step + 1);
+        notes_on.push(!debug_conn.borrow().notes_on.is_empty());
+    }
+    assert!(notes_on == vec![false, false, false, true, true, false, false, false]);
+}
+
+#[test]
+fn seed_reproduces_random_choices() {
+    use rand::Rng;
+
+    let (mut midi_a, _) = debug_controller();
+    let (mut midi_b, _) = debug_controller();
+    midi_a.set_seed(42);
+    midi_b.set_seed(42);
+    assert!(midi_a.seed() == 42);
+
+    let a: Vec<u32> = (0..16).map(|_| midi_a.rng().gen()).collect();
+    let b: Vec<u32> = (0..16).map(|_| midi_b.rng().gen()).collect();
+    assert!(a == b);
+}
+
+#[test]
+fn max_gate() {
+    let (mut controller, debug_conn) = debug_controller();
+    controller.set_max_gate(0, Some(2));
+    let note = MidiNote::new(Note::C, 4, 100);
+    let key = note.midi_value();
+
+    controller.start_note(note, 0);
+    controller.update(1);
+    assert!(debug_conn.borrow().notes_on.contains_key(&(0, key)));
+    controller.update(2);
+    assert!(debug_conn.borrow().notes_on.contains_key(&(0, key)));
+    controller.update(3);
+    assert!(debug_conn.borrow().notes_on.is_empty());
+
+    controller.play_note(note, 16, 0);
+    controller.update(4);
+    controller.update(5);
+    controller.update(6);
+    assert!(debug_conn.borrow().notes_on.is_empty());
+
+    // No limit on the other channels.
+    controller.start_note(note, 1);
+    for step in 7..20 {
+        controller.update(step);
+    }
+    assert!(debug_conn.borrow().notes_on.contains_key(&(1, key)));
+
+    // A very long max gate never releases the note.
+    controller.set_max_gate(2, Some(u32::MAX));
+    controller.start_note(note, 2);
+    controller.update(20);
+    controller.update(21);
+    assert!(debug_conn.borrow().notes_on.contains_key(&(2, key)));
+}
+
+#[test]
+fn stuck_notes() {
+    let (mut controller, debug_conn) = debug_controller();
+    controller.set_stuck_note_threshold(Some(4), true);
+    let note = MidiNote::new(Note::E, 2, 90);
+
+    controller.start_note(note, 3);
+    controller.play_note(MidiNote::new(Note::G, 2, 90), 2, 3);
+    controller.update(1);
+    let sounding: Vec<_> = controller.sounding_notes().collect();
+    assert!(sounding.len() == 2);
+    assert!(sounding[0] == (3, note, 0));
+
+    for step in 2..5 {
+        controller.update(step);
+    }
+    assert!(controller.sounding_notes().count() == 1);
+    controller.update(5);
+    assert!(controller.sounding_notes().count() == 0);
+    assert!(debug_conn.borrow().notes_on.is_empty());
+
+    // Stopping a released note does not send another note off.
+    controller.stop_note(note, 3);
+    controller.update(6);
+
+    // A very long threshold never reports the note.
+    controller.set_stuck_note_threshold(Some(u32::MAX), true);
+    controller.start_note(note, 3);
+    controller.update(7);
+    controller.update(8);
+    assert!(controller.sounding_notes().count() == 1);
+}
+
+#[test]
+fn stop_note_started_at_same_step() {
+    let (mut controller, debug_conn) = debug_controller();
+    let note = MidiNote::new(Note::C, 4, 100);
+    let key = (0, note.midi_value());
+
+    // Only the note of start_note is dropped, the note of play_note is played with its note off.
+    controller.play_note(note, 4, 0);
+    controller.start_note(note, 0);
+    controller.stop_note(note, 0);
+    controller.update(1);
+    assert!(debug_conn.borrow().notes_on.contains_key(&key));
+    for step in 2..5 {
+        controller.update(step);
+    }
+    assert!(debug_conn.borrow().notes_on.contains_key(&key));
+    controller.update(5);
+    assert!(debug_conn.borrow().notes_on.is_empty());
+    assert!(controller.sounding_notes().count() == 0);
+}
+
+#[test]
+fn cc_mapping() {
+    let mut out = vec![];
+    {
+        let mut controller = MidiController::new(crate::TextConnection::new(&mut out, false));
+        controller.add_cc_mapping(0, 1, 0, 74);
+        controller.add_cc_mapping(0, 1, 2, 71);
+        controller.send_cc(0, 1, 64);
+        assert!(controller.get_cc(0, 1) == Some(64));
+        controller.clear_cc_mappings(0, 1);
+        controller.send_cc(0, 1, 10);
+    }
+    let text = String::from_utf8(out).unwrap();
+    assert!(
+        text == "CC\tchannel: 0\tparameter: 74\tvalue: 64\n\
+                 CC\tchannel: 2\tparameter: 71\tvalue: 64\n\
+                 CC\tchannel: 0\tparameter: 1\tvalue: 10\n"
+    );
+}
+
+#[test]
+fn density() {
+    let (mut controller, _) = debug_controller();
+    controller.set_seed(7);
+    let note = MidiNote::new(Note::D, 3, 100);
+
+    controller.set_density(0.0);
+    controller.play_note(note, 1, 0);
+    controller.update(1);
+    assert!(controller.sounding_notes().count() == 0);
+
+    controller.set_density(0.5);
+    controller.set_channel_density(1, Some(0.0));
+    controller.set_channel_density(2, Some(2.0));
+    let mut played = 0;
+    for step in 2..202 {
+        controller.play_note(note, 1, 1);
+        controller.play_note(note, 1, 2);
+        controller.update(step);
+        played += controller.sounding_notes().filter(|n| n.0 == 2).count();
+        assert!(controller.sounding_notes().all(|n| n.0 != 1));
+    }
+    assert!((70..130).contains(&played));
+}
+
+#[test]
+fn note_off_after_jump() {
+    let (mut controller, debug_conn) = debug_controller();
+    controller.play_note(MidiNote::new(Note::C, 4, 100), 10, 0);
+    controller.play_note(MidiNote::new(Note::E, 4, 100), 2, 0);
+    controller.update(1);
+    assert!(debug_conn.borrow().notes_on.len() == 2);
+
+    // The note offs scheduled before the new step are sent at once.
+    controller.jump(20);
+    controller.update(21);
+    assert!(debug_conn.borrow().notes_on.is_empty());
+}
+
+#[test]
+fn note_range() {
+    let (mut controller, debug_conn) = debug_controller();
+    let low = MidiNote::new(Note::C, 3, 0);
+    let high = MidiNote::new(Note::C, 4, 0);
+    controller.set_note_range(0, Some((low, high)), RangePolicy::Clamp);
+    controller.set_note_range(1, Some((low, high)), RangePolicy::Fold);
+    controller.set_note_range(2, Some((low, high)), RangePolicy::Drop);
+
+    let note = MidiNote::new(Note::E, 5, 100);
+    for channel_id in 0..3 {
+        controller.start_note(note, channel_id);
+    }
+    controller.update(1);
+    {
+        let conn = debug_conn.borrow();
+        assert!(conn.notes_on.contains_key(&(0, high.midi_value())));
+        let folded = MidiNote::new(Note::E, 3, 100).midi_value();
+        assert!(conn.notes_on.contains_key(&(1, folded)));
+        assert!(conn.notes_on.len() == 2);
+    }
+
+    // The notes are stopped with the same range applied.
+    for channel_id in 0..3 {
+        controller.stop_note(note, channel_id);
+    }
+    controller.update(2);
+    assert!(debug_conn.borrow().notes_on.is_empty());
+}
+
+#[test]
+fn channel_velocity() {
+    let (mut controller, debug_conn) = debug_controller();
+    controller.set_fixed_velocity(0, Some(127));
+    controller.set_velocity_range(0, Some((20, 100)));
+    controller.set_velocity_range(1, Some((20, 100)));
+
+    let soft = MidiNote::new(Note::C, 4, 5);
+    let loud = MidiNote::new(Note::D, 4, 120);
+    controller.play_note(soft, 4, 0);
+    controller.play_note(soft, 4, 1);
+    controller.play_note(loud, 4, 1);
+    controller.play_note(loud, 4, 2);
+    controller.update(1);
+
+    let conn = debug_conn.borrow();
+    assert!(conn.notes_on[&(0, soft.midi_value())] == 100);
+    assert!(conn.notes_on[&(1, soft.midi_value())] == 20);
+    assert!(conn.notes_on[&(1, loud.midi_value())] == 100);
+    assert!(conn.notes_on[&(2, loud.midi_value())] == 120);
+}
+
+#[test]
+fn channel_messages() {
+    let mut out = vec![];
+    {
+        let mut controller = MidiController::new(crate::TextConnection::new(&mut out, false));
+        controller.send_pitch_bend(1, 0x3000);
+        controller.send_pitch_bend(1, u16::MAX);
+        controller.send_channel_pressure(2, 90);
+        controller.send_poly_pressure(MidiNote::new(Note::A, 3, 100), 2, 40);
+        controller.send_sysex(&[0x41, 0x10, 0x7f]);
+        controller.send_sysex(&[0x41, 0xf7]);
+    }
+    let text = String::from_utf8(out).unwrap();
+    assert!(
+        text == "Pitch bend\tchannel: 1\tvalue: 12288\n\
+                 Pitch bend\tchannel: 1\tvalue: 16383\n\
+                 Channel pressure\tchannel: 2\tpressure: 90\n\
+                 Poly pressure\tchannel: 2\tnote: A3\tpressure: 40\n\
+                 SysEx\tdata: 41 10 7f\n"
+    );
+}
+
+#[test]
+fn multi_cc_messages() {
+    let mut out = vec![];
+    {
+        let mut controller = MidiController::new(crate::TextConnection::new(&mut out, false));
+        controller.send_nrpn(0, 0x0105, 0x2000);
+        controller.send_cc14(1, 74, 0x2000);
+        controller.send_cc14(1, 16, 0x3fff);
+    }
+    let text = String::from_utf8(out).unwrap();
+    assert!(
+        text == "CC\tchannel: 0\tparameter: 99\tvalue: 2\n\
+                 CC\tchannel: 0\tparameter: 98\tvalue: 5\n\
+                 CC\tchannel: 0\tparameter: 6\tvalue: 64\n\
+                 CC\tchannel: 0\tparameter: 38\tvalue: 0\n\
+                 CC\tchannel: 0\tparameter: 101\tvalue: 127\n\
+                 CC\tchannel: 0\tparameter: 100\tvalue: 127\n\
+                 CC\tchannel: 1\tparameter: 16\tvalue: 127\n\
+                 CC\tchannel: 1\tparameter: 48\tvalue: 127\n"
+    );
+}
+
+#[test]
+fn multi_cc_messages_not_in_snapshot() {
+    let mut out = vec![];
+    {
+        let mut controller = MidiController::new(crate::TextConnection::new(&mut out, false));
+        controller.send_cc(0, 74, 20);
+        controller.send_nrpn(0, 0x0105, 0x2000);
+        controller.send_bank_select(0, 1, 2);
+        controller.send_cc14(0, 1, 0x2000);
+        assert!(controller.get_cc(0, 99).is_none());
+        assert!(controller.get_cc(0, 0).is_none());
+        assert!(controller.get_cc(0, 1).is_none());
+        controller.save_cc_snapshot("patch");
+        // Mark the end of the setup in the output
+        controller.send_program_change(1, 0);
+        controller.restore_cc_snapshot("patch", 0);
+    }
+    let text = String::from_utf8(out).unwrap();
+    let restored = text.rsplit("program: 0\n").next().unwrap();
+    assert!(restored == "CC\tchannel: 0\tparameter: 74\tvalue: 20\n");
+}
+#[test]
+fn cc_snapshot_crossfade() {
+    let (mut controller, _) = debug_controller();
+
+    controller.send_cc(0, 74, 20);
+    controller.save_cc_snapshot("verse");
+    controller.send_cc(0, 74, 100);
+    controller.save_cc_snapshot("chorus");
+
+    // The crossfade starts from the verse, not from the current value.
+    controller.send_cc(0, 74, 127);
+    controller.crossfade_cc_snapshots("verse", "chorus", 4);
+    let mut values = vec![];
+    for step in 0..4 {
+        controller.update(step + 1);
+        values.push(controller.get_cc(0, 74).unwrap());
+    }
+    assert!(values == vec![40, 60, 80, 100]);
+}
+
+#[test]
+fn mpe_zone() {
+    let (mut controller, debug_conn) = debug_controller();
+    let note = MidiNote::new(Note::C, 4, 100);
+    assert!(controller.play_mpe_note(MpeZone::Lower, note, 4).is_none());
+
+    controller.set_mpe_zone(MpeZone::Lower, 2);
+    controller.set_mpe_zone(MpeZone::Upper, 12);
+    assert!(controller.play_mpe_note(MpeZone::Lower, note, 4) == Some(1));
+    assert!(controller.play_mpe_note(MpeZone::Lower, note, 4) == Some(2));
+    assert!(controller.play_mpe_note(MpeZone::Upper, note, 4) == Some(14));
+    controller.update(1);
+    assert!(debug_conn.borrow().notes_on.len() == 3);
+
+    // The channel 2 is free again once its note ends.
+    controller.play_mpe_note(MpeZone::Lower, note, 8);
+    for step in 2..=5 {
+        controller.update(step);
+    }
+    assert!(controller.play_mpe_note(MpeZone::Lower, note, 4) == Some(2));
+}
+
+#[test]
+fn mpe_zones_overlap() {
+    let (mut controller, _) = debug_controller();
+    let note = MidiNote::new(Note::C, 4, 100);
+    let upper_channels = |controller: &mut MidiController<DebugMidiConnection>| {
+        let mut channels: Vec<_> = (0..16)
+            .filter_map(|_| controller.play_mpe_note(MpeZone::Upper, note, 1))
+            .collect();
+        channels.sort();
+        channels.dedup();
+        channels
+    };
+
+    // The lower zone uses the channels 1 to 10, the upper zone is shrunk to the channels 11 to 14.
+    controller.set_mpe_zone(MpeZone::Upper, 5);
+    controller.set_mpe_zone(MpeZone::Lower, 10);
+    assert!(upper_channels(&mut controller) == vec![11, 12, 13, 14]);
+
+    // A zone of 15 member channels disables the other zone.
+    controller.set_mpe_zone(MpeZone::Lower, 15);
+    assert!(controller.play_mpe_note(MpeZone::Upper, note, 1).is_none());
+    assert!(controller.play_mpe_note(MpeZone::Lower, note, 1) == Some(1));
+}
@@ -74,7 +74,9 @@ fn play_note_conductor() {
     }));
     let midi = MidiController::new(DebugMidiConnection(debug_conn.clone()));
     let conductor = DebugConductor1(debug_conn);
-    super::common::test_conductor(conductor, midi);
+    let summary = super::common::test_conductor(conductor, midi);
+    // Steps 0 to 10 included.
+    assert!(summary.ticks == 11);
 }
 
 struct DebugConductor2(Rc<RefCell<DebugMidiConnectionInner>>);
@@ -188,3 +190,491 @@ fn dete_track_transpose() {
     };
     super::common::test_conductor(conductor, midi);
 }
+
+struct DebugConductor4 {
+    job: Option<crate::Job<u32>>,
+    result: Option<u32>,
+}
+
+impl Conductor for DebugConductor4 {
+    fn init(&mut self, context: &mut Context<impl MidiConnection>) {
+        self.job = Some(context.spawn_job(|| (1..=10).sum()));
+    }
+
+    fn update(&mut self, context: &mut Context<impl MidiConnection>) {
+        if let Some(result) = self.job.as_mut().and_then(|j| j.try_result()) {
+            self.result = Some(result);
+        }
+        if self.result.is_some() || context.step == 96 {
+            context.quit();
+        }
+    }
+}
+
+#[test]
+fn spawn_job() {
+    let debug_conn = Rc::new(RefCell::new(DebugMidiConnectionInner {
+        notes_on: HashMap::new(),
+        start_timestamp: Instant::now(),
+    }));
+    let midi = MidiController::new(DebugMidiConnection(debug_conn));
+    let mut conductor = DebugConductor4 {
+        job: None,
+        result: None,
+    };
+    let mut ctx = Context::new(midi);
+    conductor.init(&mut ctx);
+    conductor.update(&mut ctx);
+    while conductor.result.is_none() {
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        conductor.update(&mut ctx);
+    }
+    assert!(conductor.result == Some(55));
+    assert!(conductor.job.unwrap().is_finished());
+}
+
+#[test]
+fn context_handle() {
+    let (midi, _) = super::common::debug_controller();
+    let mut ctx = Context::new(midi);
+    let handle = ctx.handle();
+
+    std::thread::spawn(move || {
+        handle.set_bpm(140.0);
+        handle.pause();
+        handle.start();
+    })
+    .join()
+    .unwrap();
+
+    ctx.poll_handle();
+    assert!(ctx.get_bpm() == 140.0);
+    assert!(ctx.transport_state() == crate::TransportState::Playing);
+
+    // Requests are applied only once.
+    ctx.pause();
+    ctx.poll_handle();
+    assert!(ctx.is_paused());
+
+    // Fractional BPM.
+    ctx.handle().set_bpm(122.5);
+    ctx.poll_handle();
+    assert!(ctx.get_bpm() == 122.5);
+    ctx.set_bpm(340.0);
+    assert!(ctx.get_bpm() == 340.0);
+    ctx.set_bpm(0.0);
+    assert!(ctx.get_bpm() == 1.0);
+}
+
+#[test]
+#[cfg(feature = "midly")]
+fn tempo_map() {
+    let mut tempo_map = crate::TempoMap::new();
+    tempo_map.add_point(1, 100.0);
+    tempo_map.add_ramp(3, 140.0);
+    tempo_map.add_point(4, 90.0);
+    assert!(tempo_map.bpm_at(0).is_none());
+    assert!(tempo_map.bpm_at(96) == Some(100.0));
+    assert!(tempo_map.bpm_at(192) == Some(120.0));
+    assert!(tempo_map.bpm_at(288) == Some(140.0));
+    assert!(tempo_map.bpm_at(383) == Some(140.0));
+    assert!(tempo_map.bpm_at(1000) == Some(90.0));
+
+    let (midi, _) = super::common::debug_controller();
+    let mut ctx = Context::new(midi);
+    ctx.set_tempo_map(Some(tempo_map));
+    ctx.start();
+    ctx.step = 240;
+    ctx.update_tempo();
+    assert!(ctx.get_bpm() == 130.0);
+
+    let path = std::env::temp_dir().join("mseq_tempo_map.mid");
+    crate::export_session(&[], 150.0, &path).unwrap();
+    let tempo_map = crate::TempoMap::load_from_midi_file(&path).unwrap();
+    assert!(tempo_map.bpm_at(0) == Some(150.0));
+}
+
+#[test]
+fn active_notes() {
+    let (midi, _) = super::common::debug_controller();
+    let mut ctx = Context::new(midi);
+    let note = MidiNote::new(Note::B, 3, 70);
+    ctx.start();
+    ctx.midi.play_note(note, 4, 2);
+    assert!(ctx.active_notes().count() == 0);
+    ctx.step += 1;
+    ctx.midi.update(ctx.step);
+    assert!(ctx.active_notes().collect::<Vec<_>>() == vec![(2, note, 0)]);
+}
+
+struct PreRollConductor;
+
+impl Conductor for PreRollConductor {
+    fn init(&mut self, context: &mut Context<impl MidiConnection>) {
+        context.set_pre_roll(3);
+        context.start();
+    }
+
+    fn update(&mut self, context: &mut Context<impl MidiConnection>) {
+        if context.step == 0 {
+            context.midi.play_note(MidiNote::new(Note::C, 4, 100), 1, 0);
+        }
+        if context.step == 2 {
+            context.quit();
+        }
+    }
+}
+
+#[test]
+fn pre_roll() {
+    let (midi, _) = super::common::debug_controller();
+    let summary = super::common::test_conductor(PreRollConductor, midi);
+    // 3 steps and 3 pre-roll clock ticks.
+    assert!(summary.ticks == 6);
+}
+
+struct ResyncConductor;
+
+impl Conductor for ResyncConductor {
+    fn init(&mut self, context: &mut Context<impl MidiConnection>) {
+        context.start();
+    }
+
+    fn update(&mut self, context: &mut Context<impl MidiConnection>) {
+        match context.step {
+            1 => context.resync(),
+            8 => context.quit(),
+            _ => (),
+        }
+    }
+}
+
+#[test]
+fn resync() {
+    let mut out = vec![];
+    crate::run_with_connection(ResyncConductor, crate::TextConnection::new(&mut out, false));
+    let text = String::from_utf8(out).unwrap();
+    assert!(text == "Start\nStop\nSong position\tposition: 1\nContinue\nStop\n");
+}
+
+#[test]
+fn locators() {
+    let mut out = vec![];
+    {
+        let midi = MidiController::new(crate::TextConnection::new(&mut out, false));
+        let mut ctx = Context::new(midi);
+        ctx.add_locator("intro", 0);
+        ctx.add_locator("chorus 2", 16);
+        ctx.start();
+        ctx.step = 50;
+        assert!(ctx.current_locator() == Some("intro"));
+        ctx.midi.start_note(MidiNote::new(Note::C, 4, 100), 0);
+        ctx.midi.update(51);
+
+        ctx.jump_to("chorus 2");
+        assert!(ctx.get_step() == 1536);
+        assert!(ctx.current_locator() == Some("chorus 2"));
+        ctx.jump_to("outro");
+        assert!(ctx.get_step() == 1536);
+    }
+    let text = String::from_utf8(out).unwrap();
+    assert!(
+        text == "Start\nNote on\tchannel: 0\tnote: C4\tvelocity: 100\n\
+                 Note off\tchannel: 0\tnote: C4\nStop\nSong position\tposition: 256\nContinue\n"
+    );
+}
+
+#[test]
+fn auto_stop() {
+    let (midi, _) = super::common::debug_controller();
+    let mut ctx = Context::new(midi);
+    ctx.set_auto_stop_after_bars(2, 1);
+    ctx.start();
+    assert!(ctx.elapsed() < std::time::Duration::from_secs(1));
+
+    ctx.steps_since_start = 96;
+    ctx.update_auto_stop();
+    assert!(ctx.running);
+
+    // All the notes are muted at the end of the fade.
+    ctx.steps_since_start = 191;
+    ctx.update_auto_stop();
+    ctx.midi.set_seed(0);
+    for _ in 0..16 {
+        ctx.midi.play_note(MidiNote::new(Note::C, 4, 100), 1, 0);
+    }
+    ctx.midi.update(1);
+    assert!(ctx.active_notes().count() == 0);
+    assert!(ctx.running);
+
+    ctx.steps_since_start = 192;
+    ctx.update_auto_stop();
+    assert!(!ctx.running);
+
+    // The density set by the user is kept once the auto stop is cleared.
+    ctx.start();
+    ctx.steps_since_start = 191;
+    ctx.update_auto_stop();
+    ctx.clear_auto_stop();
+    ctx.midi.play_note(MidiNote::new(Note::C, 4, 100), 1, 0);
+    ctx.midi.update(2);
+    assert!(ctx.active_notes().count() == 1);
+}
+
+struct IdleConductor;
+
+impl Conductor for IdleConductor {
+    fn init(&mut self, _context: &mut Context<impl MidiConnection>) {}
+
+    fn update(&mut self, _context: &mut Context<impl MidiConnection>) {}
+}
+
+#[test]
+fn run_offline() {
+    // 20 bars at 120 BPM would last 40 seconds in real time.
+    let summary = crate::run_offline(IdleConductor, crate::NullConnection, 20 * 96);
+    assert!(summary.ticks == 20 * 96);
+}
+
+// Writer recording the time of each write.
+struct TimedWriter(Rc<RefCell<Vec<(Instant, String)>>>);
+
+impl std::io::Write for TimedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let text = String::from_utf8_lossy(buf).to_string();
+        self.0.borrow_mut().push((Instant::now(), text));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+struct ArmConductor;
+
+impl Conductor for ArmConductor {
+    fn init(&mut self, context: &mut Context<impl MidiConnection>) {
+        context.start();
+    }
+
+    fn update(&mut self, context: &mut Context<impl MidiConnection>) {
+        if context.step > 0 {
+            context.quit();
+        }
+    }
+}
+
+#[test]
+fn first_clock_one_period_after_arm() {
+    let writes = Rc::new(RefCell::new(vec![]));
+    let conn = crate::TextConnection::new(TimedWriter(writes.clone()), true);
+    crate::run_with_connection(ArmConductor, conn);
+
+    let writes = writes.borrow();
+    let time_of = |message: &str| {
+        writes
+            .iter()
+            .find(|(_, m)| m.starts_with(message))
+            .unwrap()
+            .0
+    };
+    // One clock period is 20.8 ms at 120 BPM. Only a lower bound is checked since a loaded
+    // machine can delay the tick.
+    let delay = time_of("Clock") - time_of("Start");
+    assert!(delay >= std::time::Duration::from_millis(15));
+}
+
+struct PauseNoteConductor {
+    updates: u32,
+}
+
+impl Conductor for PauseNoteConductor {
+    fn init(&mut self, _context: &mut Context<impl MidiConnection>) {}
+
+    fn update(&mut self, context: &mut Context<impl MidiConnection>) {
+        match self.updates {
+            0 => {
+                context
+                    .midi
+                    .play_note(MidiNote::new(Note::C, 4, 100), 24, 0);
+                context.pause();
+            }
+            1 => context.resume(),
+            4 => context.quit(),
+            _ => (),
+        }
+        self.updates += 1;
+    }
+}
+
+#[test]
+fn pause_drops_requested_notes() {
+    let debug_conn = Rc::new(RefCell::new(DebugMidiConnectionInner {
+        notes_on: HashMap::new(),
+        start_timestamp: Instant::now(),
+    }));
+    let midi = MidiController::new(DebugMidiConnection(debug_conn.clone()));
+    super::common::test_conductor(PauseNoteConductor { updates: 0 }, midi);
+    // The note requested before the pause is never played, so it can't be left stuck.
+    assert!(debug_conn.borrow().notes_on.is_empty());
+}
+
+#[test]
+fn params() {
+    let mut out = vec![];
+    {
+        let midi = MidiController::new(crate::TextConnection::new(&mut out, false));
+        let mut ctx = Context::new(midi);
+        ctx.param("cutoff").bind_cc(0, 74);
+        ctx.param("cutoff").set(0.5);
+        ctx.param("cutoff").set(0.5);
+        ctx.param("mix").set(2.0);
+        assert!(ctx.param("mix").get() == 1.0);
+        ctx.param("mix").set(f32::NAN);
+        ctx.param("mix").set(f32::NEG_INFINITY);
+        assert!(ctx.param("mix").get() == 1.0);
+        assert!(ctx.params().take_changed() == vec!["cutoff", "mix"]);
+        assert!(ctx.params().take_changed().is_empty());
+
+        // Restore the parameters from any serde format.
+        use serde::de::value::{Error, MapDeserializer};
+        use serde::Deserialize;
+        let values = vec![("cutoff", 1.0f32)].into_iter();
+        let saved = crate::Params::deserialize(MapDeserializer::<_, Error>::new(values)).unwrap();
+        ctx.load_params(saved);
+        assert!(ctx.params().get("cutoff") == Some(1.0));
+        assert!(ctx.params().iter().count() == 2);
+    }
+    let text = String::from_utf8(out).unwrap();
+    assert!(
+        text == "CC\tchannel: 0\tparameter: 74\tvalue: 64\n\
+                 CC\tchannel: 0\tparameter: 74\tvalue: 127\n"
+    );
+}
+
+#[test]
+fn clock_reset() {
+    let mut clock = crate::clock::Clock::new(120.0);
+    std::thread::sleep(std::time::Duration::from_millis(100));
+
+    // Without the reset, the ticks would be sent at once to catch up with the sleep.
+    clock.reset();
+    let start = Instant::now();
+    clock.tick();
+    clock.tick();
+    // Two clock periods last 41.6 ms at 120 BPM, keep a margin for the timer resolution.
+    assert!(start.elapsed() >= std::time::Duration::from_millis(35));
+}
+
+struct ArpConductor {
+    notes: Vec<MidiNote>,
+}
+
+impl Conductor for ArpConductor {
+    fn init(&mut self, context: &mut Context<impl MidiConnection>) {
+        context.start();
+    }
+
+    fn update(&mut self, context: &mut Context<impl MidiConnection>) {
+        let step = context.get_step();
+        if step.is_multiple_of(6) {
+            let note = self.notes[(step / 6) as usize % self.notes.len()];
+            context.midi.play_note(note, 3, 0);
+        }
+    }
+}
+
+#[test]
+fn compare_conductors() {
+    let c = MidiNote::new(Note::C, 4, 100);
+    let e = MidiNote::new(Note::E, 4, 100);
+    let a = || ArpConductor { notes: vec![c, e] };
+    assert!(crate::compare_conductors(a(), a(), 96).is_none());
+
+    let b = ArpConductor { notes: vec![c, c] };
+    let (_, message_a, message_b) = crate::compare_conductors(a(), b, 96).unwrap();
+    assert!(message_a == "Note on\tchannel: 0\tnote: E4\tvelocity: 100");
+    assert!(message_b == "Note on\tchannel: 0\tnote: C4\tvelocity: 100");
+
+    let summary = crate::run_offline(a(), crate::NullConnection, 960);
+    assert!(summary.ticks == 960);
+}
+
+struct PauseConductor;
+
+impl Conductor for PauseConductor {
+    fn init(&mut self, context: &mut Context<impl MidiConnection>) {
+        context.set_clock_while_stopped(false);
+        context.start();
+    }
+
+    fn update(&mut self, context: &mut Context<impl MidiConnection>) {
+        match context.get_step() {
+            2 if !context.is_paused() => context.pause(),
+            2 => context.quit(),
+            _ => (),
+        }
+    }
+}
+
+#[test]
+fn clock_while_stopped() {
+    let mut out = vec![];
+    let conn = crate::TextConnection::new(&mut out, true);
+    crate::run_offline(PauseConductor, conn, 8);
+    let text = String::from_utf8(out).unwrap();
+    assert!(text == "Start\nClock\nClock\nStop\n");
+}
+
+#[test]
+fn resume_policy() {
+    let mut out = vec![];
+    {
+        let midi = MidiController::new(crate::TextConnection::new(&mut out, false));
+        let mut ctx = Context::new(midi);
+        ctx.start();
+        ctx.step = 10;
+        ctx.pause();
+        ctx.resume();
+        assert!(ctx.get_step() == 10);
+
+        ctx.set_resume_policy(crate::ResumePolicy::Restart);
+        ctx.pause();
+        ctx.resume();
+        assert!(ctx.get_step() == 0);
+        assert!(ctx.transport_state() == crate::TransportState::Playing);
+    }
+    let text = String::from_utf8(out).unwrap();
+    assert!(text == "Start\nContinue\nStart\n");
+}
+
+struct PulseConductor;
+
+impl Conductor for PulseConductor {
+    fn init(&mut self, context: &mut Context<impl MidiConnection>) {
+        context.add_pulse_clock(3, MidiNote::new(Note::C, 3, 127), 2);
+        context.start();
+    }
+
+    fn update(&mut self, context: &mut Context<impl MidiConnection>) {
+        if context.get_step() == 48 {
+            context.quit();
+        }
+    }
+}
+
+#[test]
+fn pulse_clock() {
+    let mut out = vec![];
+    crate::run_offline(
+        PulseConductor,
+        crate::TextConnection::new(&mut out, false),
+        100,
+    );
+    let text = String::from_utf8(out).unwrap();
+    let pulse = "Note on\tchannel: 3\tnote: C3\tvelocity: 127\n\
+                 Note off\tchannel: 3\tnote: C3\n";
+    // 2 PPQN: one pulse every 12 steps, from step 0 to step 48.
+    assert!(text == format!("Start\n{}Stop\n", pulse.repeat(5)));
+}
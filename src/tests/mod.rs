@@ -1,4 +1,10 @@
 #[cfg(test)]
 mod common;
 #[cfg(test)]
+mod controller_test;
+#[cfg(test)]
 mod integration_test;
+#[cfg(test)]
+mod property_test;
+#[cfg(all(test, feature = "csv", feature = "midly"))]
+mod track_test;
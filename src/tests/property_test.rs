@@ -0,0 +1,82 @@
+use proptest::prelude::*;
+
+use super::common::debug_controller;
+use crate::MidiNote;
+use crate::NoteOrder;
+use crate::RetriggerPolicy;
+
+#[derive(Clone, Debug)]
+enum Op {
+    PlayNote(u8, u32, u8),
+    PlayNoteAt(u8, u32, u32, u8),
+    StartNote(u8, u8),
+    StopNote(u8, u8),
+    MaxGate(u8, Option<u32>),
+    Update,
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        (0..128u8, 0..16u32, 0..16u8).prop_map(|(n, len, c)| Op::PlayNote(n, len, c)),
+        (0..128u8, 0..16u32, 0..16u32, 0..16u8)
+            .prop_map(|(n, offset, len, c)| Op::PlayNoteAt(n, offset, len, c)),
+        (0..128u8, 0..16u8).prop_map(|(n, c)| Op::StartNote(n, c)),
+        (0..128u8, 0..16u8).prop_map(|(n, c)| Op::StopNote(n, c)),
+        (0..16u8, proptest::option::of(0..8u32)).prop_map(|(c, g)| Op::MaxGate(c, g)),
+        Just(Op::Update),
+        Just(Op::Update),
+    ]
+}
+
+proptest! {
+    // The debug connection panics if a note off is sent for a note that is not on.
+    #[test]
+    fn every_note_on_is_stopped(
+        ops in prop::collection::vec(op(), 0..200),
+        on_first in any::<bool>(),
+        tie in any::<bool>(),
+    ) {
+        let (mut controller, debug_conn) = debug_controller();
+        if on_first {
+            controller.set_note_order(NoteOrder::OnFirst);
+        }
+        if tie {
+            controller.set_retrigger_policy(RetriggerPolicy::Tie);
+        }
+
+        let mut step = 0;
+        for op in ops {
+            match op {
+                Op::PlayNote(n, len, c) => {
+                    controller.play_note(MidiNote::from_midi_value(n, 100), len, c)
+                }
+                Op::PlayNoteAt(n, offset, len, c) => {
+                    controller.play_note_at(MidiNote::from_midi_value(n, 100), offset, len, c)
+                }
+                Op::StartNote(n, c) => controller.start_note(MidiNote::from_midi_value(n, 100), c),
+                Op::StopNote(n, c) => controller.stop_note(MidiNote::from_midi_value(n, 100), c),
+                Op::MaxGate(c, g) => controller.set_max_gate(c, g),
+                Op::Update => {
+                    step += 1;
+                    controller.update(step);
+
+                    // The sounding notes are exactly the notes on of the connection.
+                    let mut sounding: Vec<(u8, u8)> = controller
+                        .sounding_notes()
+                        .map(|(c, n, _)| (c, n.midi_value()))
+                        .collect();
+                    let mut notes_on: Vec<(u8, u8)> =
+                        debug_conn.borrow().notes_on.keys().copied().collect();
+                    sounding.sort();
+                    notes_on.sort();
+                    prop_assert_eq!(sounding, notes_on);
+                    prop_assert!(controller.sounding_notes().all(|(c, _, since)| c < 16 && since < step));
+                }
+            }
+        }
+
+        controller.stop_all_notes();
+        prop_assert!(debug_conn.borrow().notes_on.is_empty());
+        prop_assert!(controller.sounding_notes().next().is_none());
+    }
+}
@@ -0,0 +1,904 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Instant;
+
+use super::common::debug_controller;
+use super::common::DebugMidiConnection;
+use super::common::DebugMidiConnectionInner;
+use crate::DeteTrack;
+use crate::Fill;
+use crate::MidiController;
+use crate::MidiNote;
+use crate::Note;
+use crate::Track;
+use crate::TrackFormat;
+use crate::TrackIssue;
+
+fn drum_track() -> DeteTrack {
+    let kick = MidiNote::new(Note::C, 3, 100);
+    let hat = MidiNote::new(Note::FS, 3, 80);
+    DeteTrack::new(
+        48,
+        vec![(kick, 0, 6), (hat, 12, 6), (kick, 24, 6), (hat, 36, 6)],
+        Note::C,
+        9,
+        "drums",
+    )
+}
+
+#[test]
+fn fill_keeps_groove_before_fill() {
+    let fill = drum_track().new_fill(Fill::Reverse, 1, "fill");
+    for step in 0..72 {
+        assert!(fill.get_notes_start_at_step(step) == drum_track().get_notes_start_at_step(step));
+    }
+}
+
+#[test]
+fn fill_roll_crescendo() {
+    let snare = MidiNote::new(Note::D, 3, 120);
+    let fill = drum_track().new_fill(Fill::Roll(snare), 1, "fill");
+
+    // Sixteenth notes on the first half of the last beat, thirty-second notes after.
+    let roll_steps: Vec<u32> = (72..96)
+        .filter(|s| !fill.get_notes_start_at_step(*s).is_empty())
+        .collect();
+    assert!(roll_steps == vec![72, 78, 84, 87, 90, 93]);
+
+    let vels: Vec<u8> = roll_steps
+        .iter()
+        .map(|s| fill.get_notes_start_at_step(*s)[0].0.vel)
+        .collect();
+    assert!(vels.windows(2).all(|w| w[0] < w[1]));
+    assert!(vels[0] == 60);
+}
+
+#[test]
+fn fill_burst() {
+    let fill = drum_track().new_fill(Fill::Burst, 1, "fill");
+    let kick = MidiNote::new(Note::C, 3, 100);
+    let hat = MidiNote::new(Note::FS, 3, 80);
+    for s in (72..84).step_by(3) {
+        assert!(fill.get_notes_start_at_step(s) == vec![(kick, 3)]);
+    }
+    for s in (84..96).step_by(3) {
+        assert!(fill.get_notes_start_at_step(s) == vec![(hat, 3)]);
+    }
+}
+
+#[test]
+fn alternatives_follow_weights() {
+    let debug_conn = Rc::new(RefCell::new(DebugMidiConnectionInner {
+        notes_on: HashMap::new(),
+        start_timestamp: Instant::now(),
+    }));
+    let mut controller = MidiController::new(DebugMidiConnection(debug_conn.clone()));
+    controller.start();
+
+    let closed_hat = MidiNote::new(Note::FS, 3, 100);
+    let open_hat = MidiNote::new(Note::AS, 3, 100);
+    let mut track = DeteTrack::new(12, vec![], Note::C, 9, "hats");
+    track.add_alternatives(vec![(Some(closed_hat), 1.0), (Some(open_hat), 0.0)], 0, 3);
+    track.add_alternatives(vec![(Some(open_hat), 0.0), (None, 1.0)], 6, 3);
+
+    for step in 0..48 {
+        controller.play_track(&mut track);
+        controller.update(step + 1);
+        let notes_on = &debug_conn.borrow().notes_on;
+        assert!(!notes_on.contains_key(&(9, open_hat.midi_value())));
+        assert!(notes_on.contains_key(&(9, closed_hat.midi_value())) == (step % 12 < 3));
+    }
+}
+
+#[cfg(feature = "hydrogen")]
+#[test]
+fn load_hydrogen_pattern() {
+    let kick = MidiNote::new(Note::C, 3, 100);
+    let snare = MidiNote::new(Note::D, 3, 100);
+    let map = HashMap::from([(0, kick), (1, snare)]);
+    let track =
+        DeteTrack::load_hydrogen_from_file("examples/res/drums_0.h2pattern", None, &map, 9, "h2")
+            .unwrap();
+
+    assert!(track.get_notes_start_at_step(0) == vec![(kick, 6)]);
+    assert!(track.get_notes_start_at_step(24) == vec![(MidiNote::new(Note::D, 3, 50), 6)]);
+    assert!(track.get_notes_start_at_step(48) == vec![(kick, 6)]);
+    assert!(track.get_notes_start_at_step(72) == vec![(MidiNote::new(Note::D, 3, 50), 12)]);
+    // Instrument 2 is not in the map.
+    assert!(track.get_notes_start_at_step(84).is_empty());
+    // The track is one bar long.
+    assert!(track.get_notes_start_at_step(96) == vec![(kick, 6)]);
+}
+
+#[test]
+fn load_midi_sections() {
+    use midly::{MetaMessage, MidiMessage, TrackEvent, TrackEventKind};
+
+    let note_on = |delta: u32, key: u8| TrackEvent {
+        delta: delta.into(),
+        kind: TrackEventKind::Midi {
+            channel: 0.into(),
+            message: MidiMessage::NoteOn {
+                key: key.into(),
+                vel: 100.into(),
+            },
+        },
+    };
+    let note_off = |delta: u32, key: u8| TrackEvent {
+        delta: delta.into(),
+        kind: TrackEventKind::Midi {
+            channel: 0.into(),
+            message: MidiMessage::NoteOff {
+                key: key.into(),
+                vel: 0.into(),
+            },
+        },
+    };
+    let meta = |delta: u32, m| TrackEvent {
+        delta: delta.into(),
+        kind: TrackEventKind::Meta(m),
+    };
+
+    // 96 ticks per beat, 4 ticks per step.
+    let track = vec![
+        meta(0, MetaMessage::Marker(b"intro")),
+        note_on(0, 60),
+        note_off(96, 60),
+        meta(96, MetaMessage::CuePoint(b"verse")),
+        note_on(0, 62),
+        note_off(48, 62),
+        meta(144, MetaMessage::EndOfTrack),
+    ];
+    let smf = midly::Smf {
+        header: midly::Header::new(
+            midly::Format::SingleTrack,
+            midly::Timing::Metrical(96.into()),
+        ),
+        tracks: vec![track],
+    };
+    let path = std::env::temp_dir().join("mseq_load_midi_sections.mid");
+    smf.save(&path).unwrap();
+
+    let sections = DeteTrack::load_sections_from_file(&path, Note::C, 0).unwrap();
+    assert!(sections.len() == 2);
+    assert!(sections[0].get_name() == "intro");
+    assert!(sections[0].get_notes_start_at_step(0) == vec![(MidiNote::new(Note::C, 5, 100), 24)]);
+    assert!(sections[0].get_notes_start_at_step(48) == vec![(MidiNote::new(Note::C, 5, 100), 24)]);
+    assert!(sections[1].get_name() == "verse");
+    assert!(sections[1].get_notes_start_at_step(0) == vec![(MidiNote::new(Note::D, 5, 100), 12)]);
+    assert!(sections[1].get_notes_start_at_step(12).is_empty());
+}
+
+#[test]
+fn load_midi_overlapping_notes() {
+    use midly::{MidiMessage, TrackEvent, TrackEventKind};
+
+    let event = |delta: u32, message| TrackEvent {
+        delta: delta.into(),
+        kind: TrackEventKind::Midi {
+            channel: 0.into(),
+            message,
+        },
+    };
+    let note_on = |delta, vel: u8| {
+        let message = MidiMessage::NoteOn {
+            key: 60.into(),
+            vel: vel.into(),
+        };
+        event(delta, message)
+    };
+    let note_off = |delta| {
+        let message = MidiMessage::NoteOff {
+            key: 60.into(),
+            vel: 0.into(),
+        };
+        event(delta, message)
+    };
+
+    // 96 ticks per beat, 4 ticks per step. The second note starts before the first one ends.
+    let track = vec![
+        note_on(0, 100),
+        note_on(48, 80),
+        note_off(48),
+        note_off(48),
+        TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::Meta(midly::MetaMessage::EndOfTrack),
+        },
+    ];
+    let smf = midly::Smf {
+        header: midly::Header::new(
+            midly::Format::SingleTrack,
+            midly::Timing::Metrical(96.into()),
+        ),
+        tracks: vec![track],
+    };
+    let path = std::env::temp_dir().join("mseq_load_midi_overlapping_notes.mid");
+    smf.save(&path).unwrap();
+
+    assert!(DeteTrack::load_from_file(&path, Note::C, 0, "overlap").is_err());
+
+    let load = |overlap| {
+        DeteTrack::load_from_file_with_overlap(&path, Note::C, 0, "overlap", overlap).unwrap()
+    };
+    let c = |vel| MidiNote::new(Note::C, 5, vel);
+    let truncated = load(crate::OverlapPolicy::Truncate);
+    assert!(truncated.get_notes_start_at_step(0) == vec![(c(100), 12)]);
+    assert!(truncated.get_notes_start_at_step(12) == vec![(c(80), 24)]);
+    let merged = load(crate::OverlapPolicy::Merge);
+    assert!(merged.get_notes_start_at_step(0) == vec![(c(100), 36)]);
+    assert!(merged.get_notes_start_at_step(12).is_empty());
+}
+
+#[test]
+fn load_midi_resampled_ticks() {
+    use midly::{MidiMessage, TrackEvent, TrackEventKind};
+
+    let event = |delta: u32, message| TrackEvent {
+        delta: delta.into(),
+        kind: TrackEventKind::Midi {
+            channel: 0.into(),
+            message,
+        },
+    };
+    let key = 60.into();
+    let vel = 100.into();
+    // 100 ticks per beat, not a multiple of the 24 steps per beat.
+    let track = vec![
+        event(50, MidiMessage::NoteOn { key, vel }),
+        event(100, MidiMessage::NoteOff { key, vel }),
+        TrackEvent {
+            delta: 50.into(),
+            kind: TrackEventKind::Meta(midly::MetaMessage::EndOfTrack),
+        },
+    ];
+    let smf = midly::Smf {
+        header: midly::Header::new(
+            midly::Format::SingleTrack,
+            midly::Timing::Metrical(100.into()),
+        ),
+        tracks: vec![track],
+    };
+    let path = std::env::temp_dir().join("mseq_load_midi_resampled_ticks.mid");
+    smf.save(&path).unwrap();
+
+    let track = DeteTrack::load_from_file(&path, Note::C, 0, "resampled").unwrap();
+    assert!(track.len == 48);
+    assert!(track.get_notes_start_at_step(12) == vec![(MidiNote::new(Note::C, 5, 100), 24)]);
+}
+
+#[test]
+fn load_midi_timecode() {
+    use midly::{MetaMessage, MidiMessage, TrackEvent, TrackEventKind};
+
+    let event = |delta: u32, message| TrackEvent {
+        delta: delta.into(),
+        kind: TrackEventKind::Midi {
+            channel: 0.into(),
+            message,
+        },
+    };
+    let meta = |delta: u32, m| TrackEvent {
+        delta: delta.into(),
+        kind: TrackEventKind::Meta(m),
+    };
+    let key = 60.into();
+    let vel = 100.into();
+    // 25 frames of 40 ticks per second, at 60 BPM: 1000 ticks per beat.
+    let track = vec![
+        meta(0, MetaMessage::Tempo(1_000_000.into())),
+        event(0, MidiMessage::NoteOn { key, vel }),
+        event(500, MidiMessage::NoteOff { key, vel }),
+        meta(500, MetaMessage::EndOfTrack),
+    ];
+    let smf = midly::Smf {
+        header: midly::Header::new(
+            midly::Format::SingleTrack,
+            midly::Timing::Timecode(midly::Fps::Fps25, 40),
+        ),
+        tracks: vec![track],
+    };
+    let path = std::env::temp_dir().join("mseq_load_midi_timecode.mid");
+    smf.save(&path).unwrap();
+
+    let track = DeteTrack::load_from_file(&path, Note::C, 0, "timecode").unwrap();
+    assert!(track.len == 24);
+    assert!(track.get_notes_start_at_step(0) == vec![(MidiNote::new(Note::C, 5, 100), 12)]);
+}
+
+#[test]
+fn export_session_tracks() {
+    let kick = MidiNote::new(Note::C, 3, 100);
+    let bass = MidiNote::new(Note::A, 2, 90);
+    let mut drums = DeteTrack::new(24, vec![(kick, 0, 6), (kick, 12, 6)], Note::C, 9, "drums");
+    drums.add_cc(74, 64, 12);
+    let mut bassline = DeteTrack::new(48, vec![(bass, 0, 12)], Note::A, 1, "bass");
+    bassline.transpose(Some(Note::C));
+
+    let path = std::env::temp_dir().join("mseq_export_session.mid");
+    crate::export_session(&[drums, bassline], 140.0, &path).unwrap();
+
+    let bytes = std::fs::read(&path).unwrap();
+    let smf = midly::Smf::parse(&bytes).unwrap();
+    assert!(smf.header.format == midly::Format::Parallel);
+    assert!(smf.tracks.len() == 3);
+    assert!(
+        smf.tracks[1][0].kind
+            == midly::TrackEventKind::Meta(midly::MetaMessage::TrackName(b"drums"))
+    );
+    assert!(
+        smf.tracks[2][0].kind
+            == midly::TrackEventKind::Meta(midly::MetaMessage::TrackName(b"bass"))
+    );
+
+    // Transposed from A2 to C3 on channel 1.
+    assert!(
+        smf.tracks[2][1].kind
+            == midly::TrackEventKind::Midi {
+                channel: 1.into(),
+                message: midly::MidiMessage::NoteOn {
+                    key: MidiNote::new(Note::C, 3, 90).midi_value().into(),
+                    vel: 90.into(),
+                },
+            }
+    );
+
+    let drum_ticks: Vec<u32> = smf.tracks[1].iter().map(|e| e.delta.into()).collect();
+    // Name, on, off, cc, on, off, end of track
+    assert!(drum_ticks == vec![0, 0, 6, 6, 0, 6, 6]);
+    assert!(
+        smf.tracks[1][3].kind
+            == midly::TrackEventKind::Midi {
+                channel: 9.into(),
+                message: midly::MidiMessage::Controller {
+                    controller: 74.into(),
+                    value: 64.into(),
+                },
+            }
+    );
+
+    // Out of range values are not silently truncated.
+    let loud = DeteTrack::new(
+        24,
+        vec![(MidiNote::new(Note::C, 3, 200), 0, 6)],
+        Note::C,
+        0,
+        "loud",
+    );
+    assert!(crate::export_session(&[loud], 140.0, &path).is_err());
+    let mut cc = DeteTrack::new(24, vec![], Note::C, 0, "cc");
+    cc.add_cc(74, 128, 0);
+    assert!(crate::export_session(&[cc], 140.0, &path).is_err());
+    let channel = DeteTrack::new(24, vec![(kick, 0, 6)], Note::C, 16, "channel");
+    assert!(crate::export_session(&[channel], 140.0, &path).is_err());
+}
+
+#[test]
+fn validate_track() {
+    let note = MidiNote::new(Note::C, 4, 100);
+    assert!(DeteTrack::new(24, vec![(note, 0, 6)], Note::C, 0, "ok")
+        .validate()
+        .is_empty());
+
+    let mut track = DeteTrack::new(
+        24,
+        vec![
+            (MidiNote::new(Note::A, 11, 100), 0, 6),
+            (MidiNote::new(Note::A, 4, 200), 6, 6),
+            (note, 12, 0),
+            (note, 24, 6),
+        ],
+        Note::C,
+        16,
+        "bad",
+    );
+    track.add_alternatives(
+        vec![
+            (Some(note), 0.5),
+            (Some(MidiNote::new(Note::C, 4, 128)), 0.5),
+        ],
+        18,
+        0,
+    );
+    track.add_cc(74, 128, 6);
+    track.add_cc(128, 64, 30);
+    let issues = track.validate();
+    assert!(
+        issues
+            == vec![
+                TrackIssue::InvalidChannel(16),
+                TrackIssue::NoteOutOfRange {
+                    step: 0,
+                    note: MidiNote::new(Note::A, 11, 100)
+                },
+                TrackIssue::NoteOutOfRange {
+                    step: 6,
+                    note: MidiNote::new(Note::A, 4, 200)
+                },
+                TrackIssue::ZeroLengthNote { step: 12 },
+                TrackIssue::NoteAfterEnd { step: 24 },
+                TrackIssue::NoteOutOfRange {
+                    step: 18,
+                    note: MidiNote::new(Note::C, 4, 128)
+                },
+                TrackIssue::ZeroLengthNote { step: 18 },
+                TrackIssue::CcOutOfRange {
+                    step: 6,
+                    parameter: 74,
+                    value: 128
+                },
+                TrackIssue::CcOutOfRange {
+                    step: 30,
+                    parameter: 128,
+                    value: 64
+                },
+                TrackIssue::CcAfterEnd { step: 30 },
+            ]
+    );
+}
+
+#[test]
+fn validate_file() {
+    use midly::{MetaMessage, MidiMessage, TrackEvent, TrackEventKind};
+
+    let path = std::env::temp_dir().join("mseq_validate_file.csv");
+    std::fs::write(&path, "div,duration\n12,48\nx,48\n6\n0,48\n").unwrap();
+    let issues = DeteTrack::validate_file(&path, TrackFormat::ClockDiv);
+    assert!(issues.len() == 2);
+    assert!(matches!(
+        issues[0],
+        TrackIssue::InvalidRecord { line: 3, .. }
+    ));
+    assert!(matches!(
+        issues[1],
+        TrackIssue::InvalidRecord { line: 4, .. }
+    ));
+
+    let path = std::env::temp_dir().join("mseq_validate_missing.csv");
+    let issues = DeteTrack::validate_file(&path, TrackFormat::Acid);
+    assert!(matches!(issues[..], [TrackIssue::InvalidFile(_)]));
+
+    let event = |delta: u32, message| TrackEvent {
+        delta: delta.into(),
+        kind: TrackEventKind::Midi {
+            channel: 0.into(),
+            message,
+        },
+    };
+    let note_on = |key: u8| MidiMessage::NoteOn {
+        key: key.into(),
+        vel: 100.into(),
+    };
+    let note_off = |key: u8| MidiMessage::NoteOff {
+        key: key.into(),
+        vel: 0.into(),
+    };
+    // 96 ticks per beat, 4 ticks per step.
+    let track = vec![
+        event(0, note_on(60)),
+        event(24, note_on(60)),
+        event(24, note_off(62)),
+        event(24, note_off(60)),
+        TrackEvent {
+            delta: 24.into(),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        },
+    ];
+    let smf = midly::Smf {
+        header: midly::Header::new(
+            midly::Format::SingleTrack,
+            midly::Timing::Metrical(96.into()),
+        ),
+        tracks: vec![track],
+    };
+    let path = std::env::temp_dir().join("mseq_validate_file.mid");
+    smf.save(&path).unwrap();
+
+    assert!(DeteTrack::load_from_file(&path, Note::C, 0, "bad").is_err());
+    let issues = DeteTrack::validate_file(&path, TrackFormat::Midi);
+    assert!(
+        issues
+            == vec![
+                TrackIssue::DuplicateNote {
+                    step: 6,
+                    note: MidiNote::new(Note::C, 5, 100)
+                },
+                TrackIssue::WrongNoteOff {
+                    step: 12,
+                    note: MidiNote::new(Note::D, 5, 0)
+                },
+            ]
+    );
+}
+
+#[test]
+fn csv_error_location() {
+    let path = std::env::temp_dir().join("mseq_csv_error_location.csv");
+    std::fs::write(&path, "note,octave,vel\nA,4,100\nE,five,100\n").unwrap();
+
+    let err = DeteTrack::load_arp_from_file(&path, crate::ArpDiv::T8, Note::A, 0, "arp")
+        .err()
+        .unwrap();
+    assert!(err.code() == "E02");
+    match err {
+        crate::MSeqError::Reading {
+            path: p,
+            line,
+            record,
+            field,
+            ..
+        } => {
+            assert!(p == path);
+            assert!(line == Some(3));
+            assert!(record == Some(2));
+            assert!(field == Some(1));
+        }
+        _ => panic!("Unexpected error: {err}"),
+    }
+}
+
+#[test]
+fn acid_cc_columns() {
+    let path = std::env::temp_dir().join("mseq_acid_cc_columns.csv");
+    std::fs::write(
+        &path,
+        "note,octave,vel,slide,timing,cutoff,resonance\n\
+         A,2,127,false,Note,20,\n\
+         C,3,127,false,Note,,100\n\
+         A,2,127,false,Rest,80,90\n",
+    )
+    .unwrap();
+    let mut track = DeteTrack::load_acid_from_file(&path, Note::A, 0, "acid").unwrap();
+
+    let (mut controller, _) = debug_controller();
+    let mut ccs = vec![];
+    for step in 0..18 {
+        controller.play_track(&mut track);
+        ccs.push((
+            controller.get_cc(0, crate::ACID_CUTOFF_CC),
+            controller.get_cc(0, crate::ACID_RESONANCE_CC),
+        ));
+        controller.update(step + 1);
+    }
+    assert!(ccs[0] == (Some(20), None));
+    assert!(ccs[6] == (Some(20), Some(100)));
+    assert!(ccs[12] == (Some(80), Some(90)));
+
+    // Acid csv files without the cc columns are still supported.
+    DeteTrack::load_acid_from_file("examples/res/acid_0.csv", Note::A, 0, "acid").unwrap();
+}
+
+#[test]
+fn clock_div_swing() {
+    let note = MidiNote::new(Note::C, 4, 100);
+    let pattern = vec![crate::ClockDiv {
+        div: 6,
+        duration: 24,
+        probability: None,
+        swing: Some(0.34),
+    }];
+    let track = DeteTrack::new_clock_div(pattern, note, 0, "div");
+    assert!(track.get_notes_start_at_step(0) == vec![(note, 8)]);
+    assert!(track.get_notes_start_at_step(6).is_empty());
+    assert!(track.get_notes_start_at_step(8) == vec![(note, 4)]);
+    assert!(track.get_notes_start_at_step(12) == vec![(note, 8)]);
+    assert!(track.get_notes_start_at_step(20) == vec![(note, 4)]);
+
+    // A division of 0 is skipped instead of dividing by 0.
+    let pattern = vec![
+        crate::ClockDiv {
+            div: 0,
+            duration: 12,
+            probability: None,
+            swing: Some(0.5),
+        },
+        crate::ClockDiv {
+            div: 6,
+            duration: 12,
+            probability: None,
+            swing: None,
+        },
+    ];
+    let track = DeteTrack::new_clock_div(pattern, note, 0, "div");
+    assert!(track.get_notes_start_at_step(0).is_empty());
+    assert!(track.get_notes_start_at_step(12) == vec![(note, 6)]);
+}
+
+#[test]
+fn clock_div_reset() {
+    let note = MidiNote::new(Note::C, 4, 100);
+    // A trig every 5 sixteenth notes.
+    let pattern = vec![crate::ClockDiv {
+        div: 30,
+        duration: 30,
+        probability: None,
+        swing: None,
+    }];
+    let track = DeteTrack::new_clock_div(pattern, note, 0, "div").with_reset(1);
+    assert!(track.len == 96);
+    assert!(track.get_notes_start_at_step(60) == vec![(note, 30)]);
+    // The last trig is cut by the reset, and the pattern restarts on the downbeat.
+    assert!(track.get_notes_start_at_step(90) == vec![(note, 6)]);
+    assert!(track.get_notes_start_at_step(96) == vec![(note, 30)]);
+}
+
+#[test]
+fn clock_div_musical_divisions() {
+    let path = std::env::temp_dir().join("mseq_clock_div_musical_divisions.csv");
+    std::fs::write(&path, "div,duration\n1/16,1/4\n1/8.,3/4\n1/8t,24\n").unwrap();
+    let note = MidiNote::new(Note::C, 4, 100);
+    let track = DeteTrack::load_clock_div_from_file(&path, note, 0, "div").unwrap();
+    // 4 sixteenth notes, 4 dotted eighth notes, 3 eighth note triplets.
+    let trigs: Vec<u32> = (0..120)
+        .filter(|s| !track.get_notes_start_at_step(*s).is_empty())
+        .collect();
+    assert!(trigs == vec![0, 6, 12, 18, 24, 42, 60, 78, 96, 104, 112]);
+
+    std::fs::write(&path, "div,duration\n1/7,24\n").unwrap();
+    assert!(DeteTrack::load_clock_div_from_file(&path, note, 0, "div").is_err());
+
+    // Overflowing divisions are rejected instead of panicking.
+    std::fs::write(&path, "div,duration\n4294967295/1.,24\n").unwrap();
+    assert!(DeteTrack::load_clock_div_from_file(&path, note, 0, "div").is_err());
+    std::fs::write(&path, "div,duration\n1/4294967295t,24\n").unwrap();
+    assert!(DeteTrack::load_clock_div_from_file(&path, note, 0, "div").is_err());
+
+    DeteTrack::load_clock_div_from_file("examples/res/clk_div_0.csv", note, 0, "div").unwrap();
+}
+
+#[test]
+fn random_variations() {
+    use rand::SeedableRng;
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+    let notes: Vec<_> = (0..16)
+        .map(|i| (MidiNote::new(Note::C, 4, 100), 6 * i, 3))
+        .collect();
+    let track = DeteTrack::new(96, notes, Note::C, 0, "track");
+
+    let shuffled = track.shuffle_steps(&mut rng);
+    for s in (0..96).step_by(6) {
+        assert!(shuffled.get_notes_start_at_step(s).len() == 1);
+    }
+
+    assert!(track.density(1.0, &mut rng).notes.len() == 16);
+    assert!(track.density(0.0, &mut rng).notes.is_empty());
+
+    assert!(track.mutate(0.0, &mut rng).notes == track.notes);
+    let mutated = track.mutate(1.0, &mut rng);
+    assert!(mutated
+        .notes
+        .iter()
+        .all(|n| n.0.midi_value() != MidiNote::new(Note::C, 4, 100).midi_value()));
+}
+
+#[test]
+fn ignore_transpose() {
+    let kick = MidiNote::new(Note::C, 3, 100);
+    let mut drums = DeteTrack::new(24, vec![(kick, 0, 6)], Note::C, 9, "drums");
+    drums.set_ignore_transpose(true);
+    drums.transpose(Some(Note::D));
+    assert!(drums.get_notes_start_at_step(0) == vec![(kick, 6)]);
+
+    drums.set_ignore_transpose(false);
+    drums.transpose(Some(Note::D));
+    assert!(drums.get_notes_start_at_step(0) == vec![(MidiNote::new(Note::D, 3, 100), 6)]);
+}
+
+#[test]
+fn drum_channel_not_transposed() {
+    let (mut controller, debug_conn) = debug_controller();
+    controller.set_drum_channel(9, true);
+
+    let kick = MidiNote::new(Note::C, 3, 100);
+    let mut drums = DeteTrack::new(24, vec![(kick, 0, 6)], Note::C, 9, "drums");
+    drums.transpose(Some(Note::D));
+    controller.play_track(&mut drums);
+    controller.update(1);
+    assert!(debug_conn
+        .borrow()
+        .notes_on
+        .contains_key(&(9, kick.midi_value())));
+}
+
+#[test]
+fn play_step_index() {
+    let (mut controller, _) = debug_controller();
+    let a = MidiNote::new(Note::A, 3, 100);
+    let b = MidiNote::new(Note::B, 3, 100);
+    let c = MidiNote::new(Note::C, 4, 100);
+    let mut track = DeteTrack::new(24, vec![(c, 18, 6), (a, 0, 6), (b, 0, 3)], Note::C, 0, "t");
+    track.set_start_step(6);
+    assert!(track.get_notes_start_at_step(6) == vec![(a, 6), (b, 3)]);
+    assert!(track.get_notes_start_at_step(0) == vec![(c, 6)]);
+    assert!(track.get_notes_start_at_step(30) == vec![(a, 6), (b, 3)]);
+    assert!(track.get_notes_start_at_step(12).is_empty());
+
+    track.add_cc(74, 10, 12);
+    track.add_cc(74, 20, 6);
+    controller.play_track(&mut track);
+    for step in 1..=24 {
+        controller.update(step);
+        controller.play_track(&mut track);
+    }
+    assert!(controller.get_cc(0, 74) == Some(10));
+}
+
+struct CountTrack {
+    count: u8,
+}
+
+impl Track for CountTrack {
+    fn play_step(
+        &mut self,
+        step: u32,
+        midi_controller: &mut MidiController<impl crate::MidiConnection>,
+    ) {
+        if step.is_multiple_of(12) {
+            self.count += 1;
+            let note = MidiNote::new(Note::C, 3 + self.count, 100);
+            midi_controller.play_note(note, 18, 0);
+            midi_controller.send_cc(0, 74, self.count);
+        }
+    }
+
+    fn get_name(&self) -> String {
+        "count".to_string()
+    }
+}
+
+#[test]
+fn freeze_track() {
+    // Nothing is played on channel 2.
+    let frozen = DeteTrack::freeze(&mut CountTrack { count: 0 }, 24, 2, 0);
+    assert!(frozen.get_notes_start_at_step(0).is_empty());
+
+    let frozen = DeteTrack::freeze(&mut CountTrack { count: 0 }, 24, 0, 0);
+    assert!(frozen.get_name() == "count");
+    assert!(frozen.channel_id == 0);
+    assert!(frozen.get_notes_start_at_step(0) == vec![(MidiNote::new(Note::C, 4, 100), 18)]);
+    // The last note is cut at the end of the track.
+    assert!(frozen.get_notes_start_at_step(12) == vec![(MidiNote::new(Note::C, 5, 100), 12)]);
+
+    let (mut controller, _) = debug_controller();
+    let mut frozen = frozen;
+    for step in 0..13 {
+        controller.play_track(&mut frozen);
+        controller.update(step + 1);
+    }
+    assert!(controller.get_cc(0, 74) == Some(2));
+
+    // The random choices are reproduced with the same seed.
+    let closed_hat = MidiNote::new(Note::FS, 3, 100);
+    let open_hat = MidiNote::new(Note::AS, 3, 100);
+    let mut hats = DeteTrack::new(6, vec![], Note::C, 9, "hats");
+    hats.add_alternatives(vec![(Some(closed_hat), 1.0), (Some(open_hat), 1.0)], 0, 3);
+    let frozen: Vec<_> = (0..2)
+        .map(|_| DeteTrack::freeze(&mut hats, 96, 9, 42).notes)
+        .collect();
+    assert!(frozen[0].len() == 16);
+    assert!(frozen[0] == frozen[1]);
+}
+
+#[test]
+fn groove_template() {
+    let hat = |vel| MidiNote::new(Note::FS, 3, vel);
+    // Late and soft off-beats.
+    let performance = DeteTrack::new(
+        48,
+        vec![
+            (hat(120), 0, 3),
+            (hat(60), 8, 3),
+            (hat(120), 12, 3),
+            (hat(60), 20, 3),
+        ],
+        Note::C,
+        9,
+        "performance",
+    );
+    let groove = crate::GrooveTemplate::extract(&performance);
+    assert!(groove.timing(1) == 2.0);
+    assert!(groove.velocity(1) == 60.0 / 90.0);
+    assert!(groove.timing(4) == 0.0);
+    assert!(groove.velocity(4) == 1.0);
+
+    let quantized = DeteTrack::new(
+        24,
+        vec![(hat(90), 0, 3), (hat(90), 6, 3)],
+        Note::C,
+        9,
+        "hats",
+    );
+    let grooved = quantized.apply_groove(&groove, 1.0);
+    assert!(grooved.get_notes_start_at_step(0) == vec![(hat(120), 3)]);
+    assert!(grooved.get_notes_start_at_step(8) == vec![(hat(60), 3)]);
+    assert!(
+        quantized
+            .apply_groove(&groove, 0.0)
+            .get_notes_start_at_step(6)
+            == vec![(hat(90), 3)]
+    );
+}
+
+struct StepsTrack {
+    steps: Vec<u32>,
+}
+
+impl Track for StepsTrack {
+    fn play_step(&mut self, step: u32, _: &mut MidiController<impl crate::MidiConnection>) {
+        self.steps.push(step);
+    }
+}
+
+#[test]
+fn scaled_track() {
+    let (mut controller, _) = debug_controller();
+    let mut half = crate::ScaledTrack::new(StepsTrack { steps: vec![] }, 1, 2);
+    let mut double = crate::ScaledTrack::new(StepsTrack { steps: vec![] }, 2, 1);
+    for step in 0..4 {
+        controller.update(step);
+        controller.play_track(&mut half);
+        controller.play_track(&mut double);
+    }
+    assert!(half.inner().steps == vec![0, 1]);
+    assert!(double.inner().steps == (0..7).collect::<Vec<_>>());
+
+    // The new rate is only applied on the next bar.
+    half.set_rate(1, 1);
+    for step in 4..100 {
+        controller.update(step);
+        controller.play_track(&mut half);
+    }
+    assert!(half.get_rate() == (1, 1));
+    assert!(half.inner().steps.ends_with(&[46, 47, 48, 49, 50, 51]));
+}
+
+#[test]
+fn track_library() {
+    use midly::{MidiMessage, TrackEvent, TrackEventKind};
+
+    let dir = std::env::temp_dir().join("mseq_track_library");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("drums")).unwrap();
+    let save = |path: std::path::PathBuf, key: u8| {
+        let event = |delta: u32, message| TrackEvent {
+            delta: delta.into(),
+            kind: TrackEventKind::Midi {
+                channel: 0.into(),
+                message,
+            },
+        };
+        let (key, vel) = (key.into(), 100.into());
+        let track = vec![
+            event(0, MidiMessage::NoteOn { key, vel }),
+            event(24, MidiMessage::NoteOff { key, vel }),
+        ];
+        let smf = midly::Smf {
+            header: midly::Header::new(
+                midly::Format::SingleTrack,
+                midly::Timing::Metrical(24.into()),
+            ),
+            tracks: vec![track],
+        };
+        smf.save(path).unwrap();
+    };
+    save(dir.join("drums/Break.mid"), 36);
+    save(dir.join("bass.mid"), 45);
+    save(dir.join("kick.mid"), 36);
+    std::fs::write(dir.join("notes.txt"), "not a track").unwrap();
+
+    let mut library = crate::TrackLibrary::new(&dir, 1).unwrap();
+    assert!(library.names().collect::<Vec<_>>() == vec!["bass", "drums/Break", "kick"]);
+    assert!(library.search("break") == vec!["drums/Break"]);
+
+    let track = library.load("drums/Break", Note::C, 9).unwrap();
+    assert!(track.get_name() == "drums/Break");
+    assert!(track.channel_id == 9);
+    assert!(library.is_loaded("drums/Break"));
+    let bass = library.load("bass", Note::A, 1).unwrap();
+    assert!(bass.get_notes_start_at_step(0) == vec![(MidiNote::new(Note::A, 3, 100), 24)]);
+    // The capacity of the cache is 1.
+    assert!(!library.is_loaded("drums/Break"));
+    assert!(library.load("snare", Note::C, 9).is_err());
+}
@@ -1,16 +1,27 @@
-use log::{debug, warn};
+#[cfg(feature = "midly")]
+use log::debug;
+use log::warn;
+use rand::seq::SliceRandom;
+#[cfg(feature = "midly")]
 use std::collections::HashMap;
+#[cfg(feature = "midly")]
 use std::path::Path;
 
 use thiserror::Error;
 
+#[cfg(feature = "midly")]
+use crate::validate::report;
 use crate::{midi_controller::MidiController, note::Note};
-use crate::{MSeqError, MidiConnection, MidiNote};
+#[cfg(feature = "midly")]
+use crate::{MSeqError, TrackIssue};
+use crate::{MidiConnection, MidiNote};
 
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum TrackError {
     #[error("Failed to read midi file: {0}")]
     Io(#[from] std::io::Error),
+    #[cfg(feature = "midly")]
     #[error("Midly error: {0}")]
     Midly(#[from] midly::Error),
     #[error("Cannot play the same note before it ends")]
@@ -21,6 +32,23 @@ pub enum TrackError {
     BadFormat,
     #[error("Unsupported timing specification")]
     BadTiming,
+    #[error("MIDI channel or value out of range")]
+    OutOfRange,
+}
+
+/// Behavior of the MIDI file loaders when a note starts again before it ends, which happens in
+/// files exported with a sustain pedal, see [`DeteTrack::load_from_file_with_overlap`].
+#[cfg(feature = "midly")]
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OverlapPolicy {
+    /// Fail with an error, as [`DeteTrack::load_from_file`] does.
+    #[default]
+    Error,
+    /// End the sounding note when the note starts again.
+    Truncate,
+    /// Merge the overlapping notes into a single note, from the first note on to the last note
+    /// off.
+    Merge,
 }
 
 /// The Track trait can be implemented by the client. A struct with the Track trait can be passed to
@@ -48,33 +76,74 @@ pub trait Track {
     }
 }
 
+// Number of steps in a beat (24 MIDI clocks per quarter note).
+#[cfg(feature = "midly")]
+const STEPS_PER_BEAT: f64 = 24.0;
+// Tempo of a midi file without tempo event (120 BPM).
+#[cfg(feature = "midly")]
+const DEFAULT_US_PER_BEAT: u32 = 500_000;
+
+// Candidate note of an alternative step and its weight.
+type Alternative = (Option<MidiNote>, f32);
+
+// Elements of `items` whose key is `step`. `items` must be sorted by key.
+fn at_step<T>(items: &[T], step: u32, key: impl Fn(&T) -> u32) -> &[T] {
+    let start = items.partition_point(|i| key(i) < step);
+    let len = items[start..].partition_point(|i| key(i) == step);
+    &items[start..start + len]
+}
+
 /// DeteTrack implements the Track trait, so it can be passed to the MidiController to play it. It
 /// is defined by a list of notes that will always play at the same time in the track, hence the
 /// name (Deterministic Track).
 #[derive(Default, Clone)]
 pub struct DeteTrack {
-    len: u32,
-    notes: Vec<(MidiNote, u32, u32)>, // (Note, start step, length)
-    start_step: u32,
-    root: Note,
-    transpose: Option<i8>,
-    channel_id: u8,
+    pub(crate) len: u32,
+    // The notes, alternatives and CCs are sorted by step modulo len (see DeteTrack::sort_by_step)
+    // to find the ones of a step with a binary search.
+    pub(crate) notes: Vec<(MidiNote, u32, u32)>, // (Note, start step, length)
+    pub(crate) alt_notes: Vec<(Vec<Alternative>, u32, u32)>, // (Candidates, start step, length)
+    pub(crate) ccs: Vec<(u8, u8, u32)>,          // (CC parameter, value, step)
+    pub(crate) start_step: u32,
+    pub(crate) root: Note,
+    pub(crate) transpose: Option<i8>,
+    ignore_transpose: bool,
+    pub(crate) channel_id: u8,
     name: String,
 }
 
 impl Track for DeteTrack {
     fn play_step(&mut self, step: u32, midi_controller: &mut MidiController<impl MidiConnection>) {
-        let cur_step = step % self.len;
-        for n in &self.notes {
-            if (n.1 + self.start_step) % self.len == cur_step {
-                let note = self.transpose.map_or(n.0, |t| n.0.transpose(t));
-                midi_controller.play_note(note, n.2, self.channel_id)
+        let len = self.len;
+        let local_step = self.local_step(step);
+        let transpose = if midi_controller.is_drum_channel(self.channel_id) {
+            None
+        } else {
+            self.transpose
+        };
+        for cc in at_step(&self.ccs, local_step, |c| c.2 % len) {
+            midi_controller.send_cc(self.channel_id, cc.0, cc.1);
+        }
+        for n in at_step(&self.notes, local_step, |n| n.1 % len) {
+            let note = transpose.map_or(n.0, |t| n.0.transpose(t));
+            midi_controller.play_note(note, n.2, self.channel_id)
+        }
+        for n in at_step(&self.alt_notes, local_step, |n| n.1 % len) {
+            match n.0.choose_weighted(midi_controller.rng(), |c| c.1) {
+                Ok((Some(note), _)) => {
+                    let note = transpose.map_or(*note, |t| note.transpose(t));
+                    midi_controller.play_note(note, n.2, self.channel_id)
+                }
+                Ok((None, _)) => (),
+                Err(e) => warn!("Invalid alternative weights: {e}"),
             }
         }
     }
 
     fn transpose(&mut self, note: Option<Note>) {
-        self.transpose = note.map(|n| Note::transpose(self.root, n));
+        if !self.ignore_transpose {
+            self.transpose = note.map(|n| Note::transpose(self.root, n));
+        }
     }
 
     fn get_root(&self) -> Note {
@@ -100,15 +169,36 @@ impl DeteTrack {
         channel_id: u8,
         name: &str,
     ) -> Self {
-        DeteTrack {
+        let mut track = DeteTrack {
             len,
             notes,
+            alt_notes: vec![],
+            ccs: vec![],
             start_step: 0,
             root,
             transpose: None,
+            ignore_transpose: false,
             channel_id,
             name: name.to_string(),
-        }
+        };
+        track.sort_by_step();
+        track
+    }
+
+    // Sort the notes, alternatives and CCs by step modulo len. The sort is stable, the elements of
+    // a step are played in the order in which they were added. This has to be called after the
+    // steps of the notes are modified.
+    pub(crate) fn sort_by_step(&mut self) {
+        let len = self.len.max(1);
+        self.notes.sort_by_key(|n| n.1 % len);
+        self.alt_notes.sort_by_key(|n| n.1 % len);
+        self.ccs.sort_by_key(|c| c.2 % len);
+    }
+
+    // Step at which the elements played at `step` start, before the start step is applied. Panics
+    // if the track is empty, like Track::play_step.
+    fn local_step(&self, step: u32) -> u32 {
+        (step % self.len + self.len - self.start_step % self.len) % self.len
     }
 
     /// Set the root of the DeteTrack. This function does not transpose the track, it only changes
@@ -117,41 +207,209 @@ impl DeteTrack {
         self.root = note;
     }
 
+    /// Add a step starting at `start_step` with a length of `len` steps, that plays one note
+    /// chosen among `candidates` each time the track reaches it. Each candidate is given with its
+    /// weight, the probability of a candidate being chosen is its weight divided by the sum of all
+    /// the weights. A `None` candidate plays nothing. The choice is made with
+    /// [`MidiController::rng`].
+    ///
+    /// # Example
+    /// ```
+    /// use mseq::{DeteTrack, MidiNote, Note};
+    ///
+    /// let closed_hat = MidiNote::new(Note::FS, 3, 100);
+    /// let open_hat = MidiNote::new(Note::AS, 3, 100);
+    /// let mut track = DeteTrack::new(24, vec![], Note::C, 9, "hats");
+    /// // 80% closed hat, 20% open hat on the off-beat.
+    /// track.add_alternatives(vec![(Some(closed_hat), 0.8), (Some(open_hat), 0.2)], 12, 6);
+    /// ```
+    pub fn add_alternatives(
+        &mut self,
+        candidates: Vec<(Option<MidiNote>, f32)>,
+        start_step: u32,
+        len: u32,
+    ) {
+        let step = start_step % self.len.max(1);
+        let i = self
+            .alt_notes
+            .partition_point(|n| n.1 % self.len.max(1) <= step);
+        self.alt_notes.insert(i, (candidates, start_step, len));
+    }
+
+    /// If `ignore` is `true`, calls to [`Track::transpose`] have no effect on the track and the
+    /// current transposition is removed. This is useful for drum tracks, whose notes select
+    /// different drum sounds, to ignore a global key change applied to all the tracks.
+    pub fn set_ignore_transpose(&mut self, ignore: bool) {
+        self.ignore_transpose = ignore;
+        if ignore {
+            self.transpose = None;
+        }
+    }
+
+    /// Add a CC lock to the track: each time the track reaches `step`, the CC `parameter` is set
+    /// to `value` on the MIDI channel of the track.
+    pub fn add_cc(&mut self, parameter: u8, value: u8, step: u32) {
+        let i = self
+            .ccs
+            .partition_point(|c| c.2 % self.len.max(1) <= step % self.len.max(1));
+        self.ccs.insert(i, (parameter, value, step));
+    }
+
     /// Load an acid track from a midi file. Refer to `examples/midi_track.rs` for an example usage.
     /// Provide the root note of the track to allow for transposition. channel_id is the midi
     /// channel where this track will be played when passed to the MidiController.
+    #[cfg(feature = "midly")]
     pub fn load_from_file<P: AsRef<Path>>(
         filename: P,
         root: Note,
         channel_id: u8,
         name: &str,
     ) -> Result<Self, MSeqError> {
-        let bytes = fs_err::read(filename).map_err(|e| MSeqError::Track(TrackError::Io(e)))?;
-        let smf = midly::Smf::parse(&bytes).map_err(|e| MSeqError::Track(TrackError::Midly(e)))?;
+        Self::load_from_file_with_overlap(filename, root, channel_id, name, OverlapPolicy::Error)
+    }
+
+    /// Load a track from a midi file like [`DeteTrack::load_from_file`], resolving the
+    /// overlapping notes according to `overlap` instead of failing.
+    #[cfg(feature = "midly")]
+    pub fn load_from_file_with_overlap<P: AsRef<Path>>(
+        filename: P,
+        root: Note,
+        channel_id: u8,
+        name: &str,
+        overlap: OverlapPolicy,
+    ) -> Result<Self, MSeqError> {
+        let midi_file = MidiFile::parse(filename, overlap, None)?;
+        Ok(DeteTrack::new(
+            midi_file.len,
+            midi_file.notes,
+            root,
+            channel_id,
+            name,
+        ))
+    }
+
+    /// Load a midi file exported with markers (or cue points) and split it into one track per
+    /// marker. Each track is named after its marker and contains the notes starting between its
+    /// marker and the next one. If the file does not start with a marker, the notes before the
+    /// first marker are returned in a track named `"Start"`. Provide the root note of the tracks to
+    /// allow for transposition. channel_id is the midi channel where the tracks will be played
+    /// when passed to the MidiController.
+    #[cfg(feature = "midly")]
+    pub fn load_sections_from_file<P: AsRef<Path>>(
+        filename: P,
+        root: Note,
+        channel_id: u8,
+    ) -> Result<Vec<Self>, MSeqError> {
+        let midi_file = MidiFile::parse(filename, OverlapPolicy::Error, None)?;
+
+        let mut markers = midi_file.markers;
+        if markers.first().is_none_or(|m| m.1 > 0) {
+            markers.insert(0, ("Start".to_string(), 0));
+        }
+
+        let mut tracks = vec![];
+        for (i, (name, start)) in markers.iter().enumerate() {
+            let end = markers.get(i + 1).map_or(midi_file.len, |m| m.1);
+            if end <= *start {
+                continue;
+            }
+            let notes = midi_file
+                .notes
+                .iter()
+                .filter(|n| (*start..end).contains(&n.1))
+                .map(|n| (n.0, n.1 - start, n.2.min(end - n.1)))
+                .collect();
+            tracks.push(DeteTrack::new(end - start, notes, root, channel_id, name));
+        }
+        Ok(tracks)
+    }
+
+    /// Return the all `(note, length)`, that start at `step`. Transposition and start step are
+    /// taken into account. Steps added with [`DeteTrack::add_alternatives`] are not returned since
+    /// they are chosen while playing.
+    pub fn get_notes_start_at_step(&self, step: u32) -> Vec<(MidiNote, u32)> {
+        let len = self.len;
+        at_step(&self.notes, self.local_step(step), |n| n.1 % len)
+            .iter()
+            .map(|n| (self.transpose.map_or(n.0, |t| n.0.transpose(t)), n.2))
+            .collect()
+    }
+}
+
+// Content of a single track midi file.
+#[cfg(feature = "midly")]
+pub(crate) struct MidiFile {
+    pub(crate) len: u32,
+    pub(crate) notes: Vec<(MidiNote, u32, u32)>,
+    // (Marker name, step)
+    pub(crate) markers: Vec<(String, u32)>,
+}
+
+#[cfg(feature = "midly")]
+impl MidiFile {
+    // Parse the midi file. If issues is provided, the note errors are collected in it and the
+    // parsing goes on, otherwise the first one is returned.
+    pub(crate) fn parse<P: AsRef<Path>>(
+        filename: P,
+        overlap: OverlapPolicy,
+        issues: Option<&mut Vec<TrackIssue>>,
+    ) -> Result<Self, MSeqError> {
+        Self::parse_file(&filename, overlap, issues).map_err(|source| MSeqError::Track {
+            path: filename.as_ref().to_path_buf(),
+            source,
+        })
+    }
+
+    fn parse_file<P: AsRef<Path>>(
+        filename: P,
+        overlap: OverlapPolicy,
+        mut issues: Option<&mut Vec<TrackIssue>>,
+    ) -> Result<Self, TrackError> {
+        let bytes = fs_err::read(filename)?;
+        let smf = midly::Smf::parse(&bytes)?;
 
         match smf.header.format {
             midly::Format::SingleTrack => (),
-            _ => return Err(MSeqError::Track(TrackError::BadFormat)),
+            _ => return Err(TrackError::BadFormat),
         }
 
         let mut notes_map: HashMap<u8, (u8, u32, u32)> = HashMap::new();
         let mut notes: Vec<(MidiNote, u32, u32)> = vec![];
+        // Number of note offs to ignore for each note because of overlapping notes.
+        let mut extra_note_offs: HashMap<u8, u32> = HashMap::new();
+        let mut markers = vec![];
         let mut step = 0;
 
-        // 24 comes from the TimeSignature (number of clocks per beat)
-        let step_size = u16::from(if let midly::Timing::Metrical(s) = smf.header.timing {
-            s
-        } else {
-            return Err(MSeqError::Track(TrackError::BadTiming));
-        }) as u32
-            / 24;
+        // Duration of a tick, in beats for metrical timing or in seconds for timecode timing.
+        let tick_len = match smf.header.timing {
+            midly::Timing::Metrical(t) if u16::from(t) > 0 => 1.0 / u16::from(t) as f64,
+            midly::Timing::Timecode(fps, subframes) if subframes > 0 => {
+                1.0 / (fps.as_f32() as f64 * subframes as f64)
+            }
+            _ => return Err(TrackError::BadTiming),
+        };
+        let timecode = matches!(smf.header.timing, midly::Timing::Timecode(..));
+        // Tempo of the file, used to convert the timecode ticks into beats.
+        let mut us_per_beat = DEFAULT_US_PER_BEAT;
+        // The events are placed at the nearest step from their absolute time, so that the
+        // rounding errors don't accumulate when the ticks per beat are not a multiple of 24.
+        let mut exact_step: f64 = 0.0;
+        let mut max_error: f64 = 0.0;
 
         debug!("{:?}", smf.header.timing);
         let track = smf.tracks.first().ok_or(TrackError::BadFormat)?;
 
         for event in track {
             debug!("step: {}, event: {:?}", step, event);
-            let nb_clocks = u32::from(event.delta) / step_size;
+            let beats_per_tick = if timecode {
+                tick_len * 1_000_000.0 / us_per_beat as f64
+            } else {
+                tick_len
+            };
+            exact_step += u32::from(event.delta) as f64 * beats_per_tick * STEPS_PER_BEAT;
+            let next_step = exact_step.round() as u32;
+            max_error = max_error.max((exact_step - next_step as f64).abs());
+            let nb_clocks = next_step - step;
 
             // Increase duration of all the current notes
             notes_map
@@ -165,45 +423,78 @@ impl DeteTrack {
                     message,
                 } => match message {
                     midly::MidiMessage::NoteOff { key, vel: _ } => {
-                        let (midi_value, (vel, start, duration)) = notes_map
-                            .remove_entry(&key.into())
-                            .ok_or(TrackError::WrongNoteOff)?;
-                        notes.push((MidiNote::from_midi_value(midi_value, vel), start, duration));
+                        if let Some(count @ 1..) = extra_note_offs.get_mut(&key.into()) {
+                            *count -= 1;
+                            continue;
+                        }
+                        match notes_map.remove_entry(&key.into()) {
+                            Some((midi_value, (vel, start, duration))) => notes.push((
+                                MidiNote::from_midi_value(midi_value, vel),
+                                start,
+                                duration,
+                            )),
+                            None => report(
+                                &mut issues,
+                                TrackIssue::WrongNoteOff {
+                                    step,
+                                    note: MidiNote::from_midi_value(key.into(), 0),
+                                },
+                                TrackError::WrongNoteOff,
+                            )?,
+                        }
                     }
                     midly::MidiMessage::NoteOn { key, vel } => {
-                        if notes_map
-                            .insert(key.into(), (vel.into(), step, 0))
-                            .is_some()
-                        {
-                            return Err(MSeqError::Track(TrackError::DuplicateNote));
+                        let key = key.into();
+                        match (notes_map.get(&key), overlap) {
+                            (None, _) => {
+                                notes_map.insert(key, (vel.into(), step, 0));
+                            }
+                            (Some(_), OverlapPolicy::Error) => {
+                                notes_map.insert(key, (vel.into(), step, 0));
+                                report(
+                                    &mut issues,
+                                    TrackIssue::DuplicateNote {
+                                        step,
+                                        note: MidiNote::from_midi_value(key, vel.into()),
+                                    },
+                                    TrackError::DuplicateNote,
+                                )?;
+                            }
+                            (Some(&(prev_vel, start, duration)), OverlapPolicy::Truncate) => {
+                                if duration > 0 {
+                                    let prev = MidiNote::from_midi_value(key, prev_vel);
+                                    notes.push((prev, start, duration));
+                                }
+                                notes_map.insert(key, (vel.into(), step, 0));
+                                *extra_note_offs.entry(key).or_default() += 1;
+                            }
+                            (Some(_), OverlapPolicy::Merge) => {
+                                *extra_note_offs.entry(key).or_default() += 1;
+                            }
                         }
                     }
                     _ => warn!("Unsupported midi event: {:?}", event),
                 },
-                midly::TrackEventKind::Meta(m) => {
-                    if m == midly::MetaMessage::EndOfTrack {
-                        break;
-                    } else {
-                        warn!("Unsupported midi event: {:?}", event);
+                midly::TrackEventKind::Meta(m) => match m {
+                    midly::MetaMessage::EndOfTrack => break,
+                    midly::MetaMessage::Tempo(t) => us_per_beat = u32::from(t).max(1),
+                    midly::MetaMessage::Marker(text) | midly::MetaMessage::CuePoint(text) => {
+                        markers.push((String::from_utf8_lossy(text).into_owned(), step));
                     }
-                }
+                    _ => warn!("Unsupported midi event: {:?}", event),
+                },
                 _ => warn!("Unsupported midi event: {:?}", event),
             }
         }
-        Ok(DeteTrack::new(step, notes, root, channel_id, name))
-    }
 
-    /// Return the all `(note, length)`, that start at `step`. Transposition and start step are
-    /// taken into account.
-    pub fn get_notes_start_at_step(&self, step: u32) -> Vec<(MidiNote, u32)> {
-        let mut notes = vec![];
-        let cur_step = step % self.len;
-        for n in &self.notes {
-            if (n.1 + self.start_step) % self.len == cur_step {
-                let note = self.transpose.map_or(n.0, |t| n.0.transpose(t));
-                notes.push((note, n.2));
-            }
+        if max_error > 0.0 {
+            warn!("Midi file events quantized to the steps, maximum error of {max_error:.2} steps");
         }
-        notes
+
+        Ok(Self {
+            len: step,
+            notes,
+            markers,
+        })
     }
 }
@@ -0,0 +1,246 @@
+use crate::midi_controller::MAX_MIDI_CHANNEL;
+#[cfg(feature = "midly")]
+use crate::track::{MidiFile, OverlapPolicy, TrackError};
+#[cfg(any(feature = "csv", feature = "midly"))]
+use crate::Note;
+#[cfg(feature = "csv")]
+use crate::{AcidTrig, ArpDiv, ClockDiv};
+use crate::{DeteTrack, MidiNote};
+#[cfg(feature = "csv")]
+use serde::de::DeserializeOwned;
+#[cfg(any(feature = "csv", feature = "midly"))]
+use std::path::Path;
+
+const MAX_MIDI_CHANNEL_ID: u8 = MAX_MIDI_CHANNEL - 1;
+const MAX_MIDI_VALUE: u32 = 127;
+
+/// Problem found in a [`DeteTrack`] by [`DeteTrack::validate`] or in a track file by
+/// [`DeteTrack::validate_file`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum TrackIssue {
+    /// The track has a length of 0 steps and cannot be played.
+    EmptyTrack,
+    /// The MIDI channel id is greater than 15.
+    InvalidChannel(u8),
+    /// The note (or alternative note) starting at `step` cannot be sent in a MIDI message: its MIDI value or its velocity
+    /// is greater than 127.
+    NoteOutOfRange {
+        /// Start step of the note
+        step: u32,
+        /// Note
+        note: MidiNote,
+    },
+    /// The note starting at `step` has a length of 0 steps and will never be played.
+    ZeroLengthNote {
+        /// Start step of the note
+        step: u32,
+    },
+    /// The note starts at `step` which is after the end of the track.
+    NoteAfterEnd {
+        /// Start step of the note
+        step: u32,
+    },
+    /// The CC lock at `step` cannot be sent in a MIDI message: its parameter or its value is
+    /// greater than 127.
+    CcOutOfRange {
+        /// Step of the CC lock
+        step: u32,
+        /// CC parameter
+        parameter: u8,
+        /// CC value
+        value: u8,
+    },
+    /// The CC lock at `step` is after the end of the track and will never be sent.
+    CcAfterEnd {
+        /// Step of the CC lock
+        step: u32,
+    },
+    /// The file cannot be read or is not in the expected format.
+    InvalidFile(String),
+    /// The record at `line` of a csv file cannot be read, e.g. because of a missing or invalid
+    /// field.
+    InvalidRecord {
+        /// Line of the record in the file
+        line: u64,
+        /// Description of the problem
+        error: String,
+    },
+    /// A note of a midi file starts at `step` before the end of the same note.
+    DuplicateNote {
+        /// Step of the note on
+        step: u32,
+        /// Note
+        note: MidiNote,
+    },
+    /// A note of a midi file ends at `step` without having been started.
+    WrongNoteOff {
+        /// Step of the note off
+        step: u32,
+        /// Note
+        note: MidiNote,
+    },
+}
+
+/// Format of a track file checked by [`DeteTrack::validate_file`].
+#[cfg(any(feature = "csv", feature = "midly"))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TrackFormat {
+    /// Csv file loaded with [`DeteTrack::load_acid_from_file`].
+    #[cfg(feature = "csv")]
+    Acid,
+    /// Csv file loaded with [`DeteTrack::load_arp_from_file`].
+    #[cfg(feature = "csv")]
+    Arp,
+    /// Csv file loaded with [`DeteTrack::load_clock_div_from_file`].
+    #[cfg(feature = "csv")]
+    ClockDiv,
+    /// Midi file loaded with [`DeteTrack::load_from_file`].
+    #[cfg(feature = "midly")]
+    Midi,
+}
+
+impl DeteTrack {
+    /// Check that the track can be played as expected and return every problem found. An empty
+    /// list means that the track is valid. This is meant to be called right after loading the
+    /// tracks, to find all the problems at once before playing.
+    pub fn validate(&self) -> Vec<TrackIssue> {
+        let mut issues = vec![];
+
+        if self.len == 0 {
+            issues.push(TrackIssue::EmptyTrack);
+        }
+        if self.channel_id > MAX_MIDI_CHANNEL_ID {
+            issues.push(TrackIssue::InvalidChannel(self.channel_id));
+        }
+
+        let alt_notes = self.alt_notes.iter().map(|(candidates, step, len)| {
+            let notes = candidates.iter().filter_map(|c| c.0).collect();
+            (notes, *step, *len)
+        });
+        // Report the issues of the notes in the order of their steps.
+        let mut notes = self.notes.clone();
+        notes.sort_by_key(|n| n.1);
+        let notes = notes
+            .into_iter()
+            .map(|(note, step, len)| (vec![note], step, len));
+        for (notes, step, len) in notes.chain(alt_notes) {
+            for note in notes {
+                let midi_value = u8::from(note.note) as u32 + 12 * note.octave as u32;
+                if midi_value > MAX_MIDI_VALUE || note.vel as u32 > MAX_MIDI_VALUE {
+                    issues.push(TrackIssue::NoteOutOfRange { step, note });
+                }
+            }
+            if len == 0 {
+                issues.push(TrackIssue::ZeroLengthNote { step });
+            }
+            if step >= self.len && self.len > 0 {
+                issues.push(TrackIssue::NoteAfterEnd { step });
+            }
+        }
+
+        for &(parameter, value, step) in &self.ccs {
+            if parameter as u32 > MAX_MIDI_VALUE || value as u32 > MAX_MIDI_VALUE {
+                issues.push(TrackIssue::CcOutOfRange {
+                    step,
+                    parameter,
+                    value,
+                });
+            }
+            if step >= self.len && self.len > 0 {
+                issues.push(TrackIssue::CcAfterEnd { step });
+            }
+        }
+
+        issues
+    }
+
+    /// Check the track file `filename` in the given `format` and return every problem found,
+    /// instead of stopping at the first one like the loaders do. The issues of the loaded track
+    /// (see [`DeteTrack::validate`]) are reported as well. An empty list means that the file can be
+    /// loaded and played. This is meant to be called before a performance, as a dry run of the
+    /// loaders.
+    #[cfg(any(feature = "csv", feature = "midly"))]
+    pub fn validate_file<P: AsRef<Path>>(filename: P, format: TrackFormat) -> Vec<TrackIssue> {
+        let filename = filename.as_ref();
+        let mut issues = vec![];
+        let name = "validate";
+        let track = match format {
+            #[cfg(feature = "csv")]
+            TrackFormat::Acid => {
+                let pattern = read_records::<AcidTrig>(filename, &mut issues);
+                DeteTrack::new_acid(pattern, Note::C, 0, name)
+            }
+            #[cfg(feature = "csv")]
+            TrackFormat::Arp => {
+                let pattern = read_records::<MidiNote>(filename, &mut issues);
+                DeteTrack::new_arp(pattern, ArpDiv::default(), Note::C, 0, name)
+            }
+            #[cfg(feature = "csv")]
+            TrackFormat::ClockDiv => {
+                let pattern = read_records::<ClockDiv>(filename, &mut issues);
+                let note = MidiNote::new(Note::C, 4, 100);
+                DeteTrack::new_clock_div(pattern, note, 0, name)
+            }
+            #[cfg(feature = "midly")]
+            TrackFormat::Midi => {
+                match MidiFile::parse(filename, OverlapPolicy::Error, Some(&mut issues)) {
+                    Ok(midi_file) => {
+                        DeteTrack::new(midi_file.len, midi_file.notes, Note::C, 0, name)
+                    }
+                    Err(e) => {
+                        issues.push(TrackIssue::InvalidFile(e.to_string()));
+                        return issues;
+                    }
+                }
+            }
+        };
+        if issues
+            .iter()
+            .all(|i| !matches!(i, TrackIssue::InvalidFile(_)))
+        {
+            issues.extend(track.validate());
+        }
+        issues
+    }
+}
+
+// Read all the valid records of a csv file, the invalid ones are reported in issues.
+#[cfg(feature = "csv")]
+fn read_records<T: DeserializeOwned>(filename: &Path, issues: &mut Vec<TrackIssue>) -> Vec<T> {
+    let mut rdr = match csv::Reader::from_path(filename) {
+        Ok(rdr) => rdr,
+        Err(e) => {
+            issues.push(TrackIssue::InvalidFile(e.to_string()));
+            return vec![];
+        }
+    };
+    rdr.deserialize()
+        .filter_map(|record| {
+            record
+                .map_err(|e| {
+                    issues.push(TrackIssue::InvalidRecord {
+                        line: e.position().map_or(0, |p| p.line()),
+                        error: e.to_string(),
+                    })
+                })
+                .ok()
+        })
+        .collect()
+}
+
+// Report a problem of a midi file, or fail if the issues are not collected.
+#[cfg(feature = "midly")]
+pub(crate) fn report(
+    issues: &mut Option<&mut Vec<TrackIssue>>,
+    issue: TrackIssue,
+    error: TrackError,
+) -> Result<(), TrackError> {
+    match issues {
+        Some(issues) => {
+            issues.push(issue);
+            Ok(())
+        }
+        None => Err(error),
+    }
+}